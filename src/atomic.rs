@@ -0,0 +1,82 @@
+//! Crash-safe file writes.
+//!
+//! Contents are written to a temporary file in the destination directory, synced to disk and then
+//! renamed over the destination, so the destination always holds either the old or the new
+//! contents in full, even if passage crashes or the disk fills up midway. Newly created files are
+//! readable and writable only by their owner.
+
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// New contents for a file, written in pieces and only put in place by [`Writer::commit`].
+///
+/// Dropping the writer without committing it discards the new contents.
+pub struct Writer {
+    file: NamedTempFile,
+    path: PathBuf,
+}
+
+impl Writer {
+    /// Start writing new contents for `path`.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Writer> {
+        let path = path.as_ref().to_path_buf();
+        let file = NamedTempFile::new_in(parent(&path))?;
+        Ok(Writer { file, path })
+    }
+
+    /// Put the new contents in place. Unless `replace` is set, fails with
+    /// [`io::ErrorKind::AlreadyExists`] if the file already exists.
+    pub fn commit(self, replace: bool) -> io::Result<()> {
+        self.file.as_file().sync_all()?;
+        if replace {
+            self.file.persist(&self.path).map_err(|e| e.error)?;
+        } else {
+            self.file
+                .persist_noclobber(&self.path)
+                .map_err(|e| e.error)?;
+        }
+        sync_dir(&self.path)
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Flush the directory entry for `path` to disk so that a rename into it is durable.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(parent(path))?.sync_all()?;
+
+    Ok(())
+}
+
+/// Atomically replace the contents of `path`, creating it if it does not exist.
+pub fn write<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    let mut writer = Writer::new(path)?;
+    writer.write_all(contents)?;
+    writer.commit(true)
+}
+
+/// Atomically create `path` with the given contents.
+///
+/// Fails with [`io::ErrorKind::AlreadyExists`] if `path` already exists.
+pub fn write_new<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    let mut writer = Writer::new(path)?;
+    writer.write_all(contents)?;
+    writer.commit(false)
+}
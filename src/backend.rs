@@ -0,0 +1,236 @@
+//! Storage for the encrypted items of a password store.
+//!
+//! A [`StoreBackend`] only ever handles ciphertext: all encryption and decryption happens in
+//! [`PasswordStore`](crate::PasswordStore), so a backend can be implemented without touching any
+//! cryptographic code.
+
+mod filesystem;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use filesystem::FileBackend;
+pub use memory::MemoryBackend;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::item::ItemName;
+
+/// The file holding the database of a store using the SQLite backend, relative to the store's
+/// directory.
+#[cfg(feature = "sqlite")]
+pub const SQLITE_DATABASE: &str = "passage.db";
+
+/// The kinds of backend a store on disk can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// One age file per item, see [`FileBackend`].
+    Files,
+    /// A single SQLite database, see [`SqliteBackend`].
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl BackendKind {
+    /// The kind of backend used by the store in `dir`. A store that does not exist yet uses
+    /// [`BackendKind::Files`].
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    pub fn detect(dir: &Path) -> BackendKind {
+        #[cfg(feature = "sqlite")]
+        if dir.join(SQLITE_DATABASE).exists() {
+            return BackendKind::Sqlite;
+        }
+
+        BackendKind::Files
+    }
+
+    /// Open the store in `dir` with this kind of backend.
+    pub fn open(self, dir: PathBuf) -> Result<Box<dyn StoreBackend>, Error> {
+        match self {
+            BackendKind::Files => Ok(Box::new(FileBackend::open(dir)?)),
+            #[cfg(feature = "sqlite")]
+            BackendKind::Sqlite => Ok(Box::new(SqliteBackend::open(dir.join(SQLITE_DATABASE))?)),
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "files" => Ok(BackendKind::Files),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(BackendKind::Sqlite),
+            _ => Err(format!("unknown backend {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Files => write!(f, "files"),
+            #[cfg(feature = "sqlite")]
+            BackendKind::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+/// Where a password store keeps its items.
+///
+/// A backend maps item names to opaque ciphertext blobs. It also holds a few named metadata blobs
+/// describing the store itself, such as its list of recipients (`public-keys`).
+pub trait StoreBackend: Send + Sync {
+    /// The names of all items, in any order.
+    fn list(&self) -> Result<Vec<ItemName>, Error>;
+
+    /// Whether the given item exists.
+    fn exists(&self, name: &ItemName) -> Result<bool, Error>;
+
+    /// Read the ciphertext of an item, failing with [`Error::ItemNotFound`] if it does not exist.
+    fn read(&self, name: &ItemName) -> Result<Vec<u8>, Error>;
+
+    /// Open the ciphertext of an item for reading, failing with [`Error::ItemNotFound`] if it does
+    /// not exist.
+    ///
+    /// The default implementation reads the whole ciphertext with [`StoreBackend::read`]; backends
+    /// that can should override it so that large items are not held in memory.
+    fn reader(&self, name: &ItemName) -> Result<Box<dyn Read + '_>, Error> {
+        Ok(Box::new(io::Cursor::new(self.read(name)?)))
+    }
+
+    /// Start writing the ciphertext of an item. Nothing changes until the writer is committed,
+    /// which replaces the item if `replace` is set and otherwise fails with
+    /// [`Error::ItemAlreadyExists`] if it exists.
+    ///
+    /// The default implementation collects the ciphertext in memory and then calls
+    /// [`StoreBackend::write`] or [`StoreBackend::create`]; backends that can should override it.
+    fn writer(&self, name: &ItemName, replace: bool) -> Result<Box<dyn ItemWriter + '_>, Error> {
+        Ok(Box::new(BufferedWriter::new(name, move |name, data| {
+            if replace {
+                self.write(name, data)
+            } else {
+                self.create(name, data)
+            }
+        })))
+    }
+
+    /// Add a new item, failing with [`Error::ItemAlreadyExists`] if it already exists.
+    fn create(&self, name: &ItemName, data: &[u8]) -> Result<(), Error>;
+
+    /// Write the ciphertext of an item, replacing it if it already exists.
+    fn write(&self, name: &ItemName, data: &[u8]) -> Result<(), Error>;
+
+    /// Replace the ciphertexts of several items at once.
+    ///
+    /// Either every item is replaced or, if an error is returned, none of them are.
+    fn replace_all(&self, items: &[(ItemName, Vec<u8>)]) -> Result<(), Error>;
+
+    /// Start replacing the ciphertexts of several items at once, writing them one at a time.
+    ///
+    /// The default implementation collects the ciphertexts in memory and then calls
+    /// [`StoreBackend::replace_all`]; backends that can should override it.
+    fn replacement(&self) -> Result<Box<dyn Replacement + '_>, Error> {
+        Ok(Box::new(BufferedReplacement {
+            backend: self,
+            items: Mutex::new(vec![]),
+        }))
+    }
+
+    /// Remove an item, failing with [`Error::ItemNotFound`] if it does not exist.
+    fn delete(&self, name: &ItemName) -> Result<(), Error>;
+
+    /// Rename an item, failing with [`Error::ItemAlreadyExists`] if `to` already exists.
+    fn rename(&self, from: &ItemName, to: &ItemName) -> Result<(), Error>;
+
+    /// Read a metadata blob, or `None` if it has never been written.
+    fn read_meta(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Write a metadata blob, replacing any previous value.
+    fn write_meta(&self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Describe every way in which the stored data could be modified by another user.
+    fn check_permissions(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// The ciphertext of an item being written, see [`StoreBackend::writer`].
+pub trait ItemWriter: Write {
+    /// Store what was written as the item's ciphertext. Dropping the writer instead leaves the
+    /// item as it was.
+    fn commit(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// New ciphertexts for several items, see [`StoreBackend::replacement`].
+pub trait Replacement: Sync {
+    /// Start writing the new ciphertext of an item. Committing the writer only stages the item;
+    /// several items can be staged at once from different threads.
+    fn writer(&self, name: &ItemName) -> Result<Box<dyn ItemWriter + '_>, Error>;
+
+    /// Replace every staged item. Either every item is replaced or, if an error is returned, none
+    /// of them are. Dropping the replacement instead leaves every item as it was.
+    fn commit(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// The ciphertext of an item collected in memory and handed to `store` when committed.
+struct BufferedWriter<F> {
+    name: ItemName,
+    data: Vec<u8>,
+    store: F,
+}
+
+impl<F: FnOnce(&ItemName, &[u8]) -> Result<(), Error>> BufferedWriter<F> {
+    fn new(name: &ItemName, store: F) -> BufferedWriter<F> {
+        BufferedWriter {
+            name: name.clone(),
+            data: vec![],
+            store,
+        }
+    }
+}
+
+impl<F> Write for BufferedWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnOnce(&ItemName, &[u8]) -> Result<(), Error>> ItemWriter for BufferedWriter<F> {
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        (self.store)(&self.name, &self.data)
+    }
+}
+
+struct BufferedReplacement<'a, B: ?Sized> {
+    backend: &'a B,
+    items: Mutex<Vec<(ItemName, Vec<u8>)>>,
+}
+
+impl<B: StoreBackend + ?Sized> Replacement for BufferedReplacement<'_, B> {
+    fn writer(&self, name: &ItemName) -> Result<Box<dyn ItemWriter + '_>, Error> {
+        Ok(Box::new(BufferedWriter::new(name, move |name, data| {
+            self.items
+                .lock()
+                .unwrap()
+                .push((name.clone(), data.to_vec()));
+            Ok(())
+        })))
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.backend.replace_all(&self.items.into_inner().unwrap())
+    }
+}
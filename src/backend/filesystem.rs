@@ -0,0 +1,550 @@
+use std::fs::{self, DirEntry, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{ItemWriter, Replacement, StoreBackend};
+use crate::error::Error;
+use crate::item::ItemName;
+use crate::{atomic, perms};
+
+/// Lists the items whose re-encrypted copies are ready to replace the originals.
+///
+/// Its presence means a re-encryption was committed but possibly not finished.
+const REENCRYPT_JOURNAL: &str = ".reencrypt";
+
+/// The path to which the re-encrypted copy of an item is written before it is committed.
+fn staged_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(format!(".{}.new", file_name))
+}
+
+/// An item being written to a temporary file next to its final location.
+struct FileWriter {
+    file: atomic::Writer,
+    name: ItemName,
+    replace: bool,
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl ItemWriter for FileWriter {
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        match self.file.commit(self.replace) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(Error::ItemAlreadyExists(self.name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The re-encrypted copy of an item being written to a temporary file next to its staged path.
+struct StagedWriter<'a> {
+    file: atomic::Writer,
+    path: PathBuf,
+    staged: &'a Mutex<Vec<PathBuf>>,
+}
+
+impl Write for StagedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl ItemWriter for StagedWriter<'_> {
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.file.commit(true)?;
+        self.staged.lock().unwrap().push(self.path);
+        Ok(())
+    }
+}
+
+/// Items whose new contents are written next to the originals, see
+/// [`FileBackend::replacement`].
+struct FileReplacement<'a> {
+    backend: &'a FileBackend,
+    /// The paths of the items whose staged copies have been written.
+    staged: Mutex<Vec<PathBuf>>,
+}
+
+impl Replacement for FileReplacement<'_> {
+    fn writer(&self, name: &ItemName) -> Result<Box<dyn ItemWriter + '_>, Error> {
+        let path = self.backend.path(name)?;
+        perms::create_dir_all(path.parent().unwrap())?;
+
+        Ok(Box::new(StagedWriter {
+            file: atomic::Writer::new(staged_path(&path))?,
+            path,
+            staged: &self.staged,
+        }))
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        let staged = std::mem::take(&mut *self.staged.lock().unwrap());
+        let journal: String = staged
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.backend.dir).ok())
+            .map(|path| format!("{}\n", path.display()))
+            .collect();
+        if let Err(e) = atomic::write(self.backend.dir.join(REENCRYPT_JOURNAL), journal.as_bytes())
+        {
+            for path in &staged {
+                let _ = fs::remove_file(staged_path(path));
+            }
+            return Err(e.into());
+        }
+
+        self.backend.recover()
+    }
+}
+
+impl Drop for FileReplacement<'_> {
+    fn drop(&mut self) {
+        for path in self.staged.get_mut().unwrap().iter() {
+            let _ = fs::remove_file(staged_path(path));
+        }
+    }
+}
+
+/// The default backend, storing each item as an age file named after the item in a directory
+/// tree. Metadata is kept in hidden files at the root of the tree, e.g. `.public-keys`.
+pub struct FileBackend {
+    dir: PathBuf,
+}
+
+impl FileBackend {
+    /// Use the store in `dir`, which need not exist yet.
+    pub fn new(dir: PathBuf) -> FileBackend {
+        FileBackend { dir }
+    }
+
+    /// Use the existing store in `dir`, first finishing any interrupted re-encryption.
+    pub fn open(dir: PathBuf) -> Result<FileBackend, Error> {
+        let backend = FileBackend::new(dir);
+        backend.recover()?;
+        Ok(backend)
+    }
+
+    /// The root directory of the store.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path of the file holding the given item.
+    ///
+    /// Fails if the path leads outside of the store through a symlink.
+    fn path(&self, name: &ItemName) -> Result<PathBuf, Error> {
+        let path = self.dir.join(format!("{}.age", name));
+
+        // Resolve the longest prefix of the path that exists
+        let mut existing = path.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => return Ok(path),
+            }
+        }
+
+        let outside = || {
+            Error::InvalidItemName(
+                name.to_string(),
+                "name resolves to a path outside of the password store",
+            )
+        };
+
+        let root = self.dir.canonicalize().map_err(|_| outside())?;
+        if !existing
+            .canonicalize()
+            .map_err(|_| outside())?
+            .starts_with(root)
+        {
+            return Err(outside());
+        }
+
+        Ok(path)
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!(".{}", key))
+    }
+
+    /// Remove the directories containing `path` that are left empty, up to the root of the store.
+    fn remove_empty_parents(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(parent) = dir {
+            if parent == self.dir || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+
+    fn items(&self) -> io::Result<Vec<DirEntry>> {
+        fn scan(dir: &Path, entries: &mut Vec<DirEntry>) -> io::Result<()> {
+            for entry in fs::read_dir(dir)?
+                .filter_map(|e| e.ok())
+                .collect::<Vec<DirEntry>>()
+                .into_iter()
+            {
+                let path = entry.path();
+                if path.is_dir() {
+                    scan(&path, entries)?;
+                } else if let Some(ext) = path.extension() {
+                    if ext == "age" {
+                        entries.push(entry);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        let mut entries: Vec<DirEntry> = vec![];
+        if self.dir.exists() {
+            scan(&self.dir, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    /// Finish a re-encryption that was committed but interrupted before every item was replaced.
+    ///
+    /// The journal is in the store and so may have been written by someone else: lines that are
+    /// not the file of a valid item inside the store are skipped.
+    pub fn recover(&self) -> Result<(), Error> {
+        let journal_path = self.dir.join(REENCRYPT_JOURNAL);
+        let journal = match fs::read_to_string(&journal_path) {
+            Ok(journal) => journal,
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => return Ok(()),
+                _ => return Err(e.into()),
+            },
+        };
+
+        for line in journal.lines() {
+            let path = match line.strip_suffix(".age").map(ItemName::new) {
+                Some(Ok(name)) => match self.path(&name) {
+                    Ok(path) => path,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            let staged = staged_path(&path);
+            if staged.exists() {
+                fs::rename(&staged, &path)?;
+                atomic::sync_dir(&path)?;
+            }
+        }
+
+        fs::remove_file(&journal_path)?;
+        atomic::sync_dir(&journal_path)?;
+
+        Ok(())
+    }
+}
+
+impl StoreBackend for FileBackend {
+    fn list(&self) -> Result<Vec<ItemName>, Error> {
+        let list = self
+            .items()?
+            .iter()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name = path.strip_prefix(&self.dir).ok()?.with_extension("");
+                ItemName::new(name.to_str()?).ok()
+            })
+            .collect();
+        Ok(list)
+    }
+
+    fn exists(&self, name: &ItemName) -> Result<bool, Error> {
+        Ok(self.path(name)?.exists())
+    }
+
+    fn read(&self, name: &ItemName) -> Result<Vec<u8>, Error> {
+        match fs::read(self.path(name)?) {
+            Ok(data) => Ok(data),
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => Err(Error::ItemNotFound(name.to_string())),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    fn reader(&self, name: &ItemName) -> Result<Box<dyn Read + '_>, Error> {
+        match File::open(self.path(name)?) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => Err(Error::ItemNotFound(name.to_string())),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    fn writer(&self, name: &ItemName, replace: bool) -> Result<Box<dyn ItemWriter + '_>, Error> {
+        let path = self.path(name)?;
+        perms::create_dir_all(path.parent().unwrap())?;
+
+        Ok(Box::new(FileWriter {
+            file: atomic::Writer::new(path)?,
+            name: name.clone(),
+            replace,
+        }))
+    }
+
+    fn create(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        let path = self.path(name)?;
+        perms::create_dir_all(path.parent().unwrap())?;
+
+        if let Err(e) = atomic::write_new(&path, data) {
+            match e.kind() {
+                io::ErrorKind::AlreadyExists => {
+                    return Err(Error::ItemAlreadyExists(name.to_string()))
+                }
+                _ => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        let path = self.path(name)?;
+        perms::create_dir_all(path.parent().unwrap())?;
+        atomic::write(path, data)?;
+
+        Ok(())
+    }
+
+    fn replace_all(&self, items: &[(ItemName, Vec<u8>)]) -> Result<(), Error> {
+        let replacement = self.replacement()?;
+        for (name, data) in items {
+            let mut writer = replacement.writer(name)?;
+            writer.write_all(data)?;
+            writer.commit()?;
+        }
+        replacement.commit()
+    }
+
+    /// New contents are first written next to the originals. Only once every item has been
+    /// written successfully is the journal written and the originals replaced, so a failure midway
+    /// leaves the store untouched. If passage is interrupted after the journal is written, the
+    /// replacement is completed by [`FileBackend::recover`] the next time.
+    fn replacement(&self) -> Result<Box<dyn Replacement + '_>, Error> {
+        self.recover()?;
+
+        Ok(Box::new(FileReplacement {
+            backend: self,
+            staged: Mutex::new(vec![]),
+        }))
+    }
+
+    fn delete(&self, name: &ItemName) -> Result<(), Error> {
+        let path = self.path(name)?;
+        if let Err(e) = fs::remove_file(&path) {
+            match e.kind() {
+                io::ErrorKind::NotFound => return Err(Error::ItemNotFound(name.to_string())),
+                _ => return Err(e.into()),
+            }
+        }
+        self.remove_empty_parents(&path);
+
+        Ok(())
+    }
+
+    fn rename(&self, from: &ItemName, to: &ItemName) -> Result<(), Error> {
+        let from_path = self.path(from)?;
+        let to_path = self.path(to)?;
+        if !from_path.exists() {
+            return Err(Error::ItemNotFound(from.to_string()));
+        }
+        if to_path.exists() {
+            return Err(Error::ItemAlreadyExists(to.to_string()));
+        }
+
+        perms::create_dir_all(to_path.parent().unwrap())?;
+        fs::rename(&from_path, &to_path)?;
+        atomic::sync_dir(&to_path)?;
+        self.remove_empty_parents(&from_path);
+
+        Ok(())
+    }
+
+    fn read_meta(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.meta_path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => Ok(None),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    fn write_meta(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        perms::create_dir_all(&self.dir)?;
+        atomic::write(self.meta_path(key), data)?;
+
+        Ok(())
+    }
+
+    fn check_permissions(&self) -> Vec<String> {
+        [self.dir.clone(), self.meta_path("public-keys")]
+            .iter()
+            .filter_map(perms::check)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt;
+    use age::x25519::Identity;
+    use std::env;
+
+    fn temp_backend(name: &str) -> FileBackend {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        FileBackend::new(dir)
+    }
+
+    #[test]
+    fn replace_all_failure_leaves_store_untouched() -> Result<(), Error> {
+        let backend = temp_backend("passage-replace-failure");
+        let a: ItemName = "a".parse()?;
+        backend.create(&a, b"old a")?;
+
+        // A directory where the staged copy of b would go makes staging fail
+        let b: ItemName = "b".parse()?;
+        fs::create_dir_all(staged_path(&backend.path(&b)?))?;
+
+        let items = vec![(a.clone(), b"new a".to_vec()), (b, b"new b".to_vec())];
+        assert!(backend.replace_all(&items).is_err());
+
+        assert_eq!(backend.read(&a)?, b"old a");
+        assert!(!staged_path(&backend.path(&a)?).exists());
+        assert!(!backend.dir.join(REENCRYPT_JOURNAL).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn recover_stays_inside_the_store() -> Result<(), Error> {
+        let backend = temp_backend("passage-recover-traversal");
+        let outside = env::temp_dir().join("passage-recover-outside");
+        let _ = fs::remove_dir_all(&outside);
+        fs::create_dir_all(&outside)?;
+        let target = outside.join("authorized_keys.age");
+        fs::write(staged_path(&target), "attacker")?;
+
+        let journal = format!(
+            "../passage-recover-outside/authorized_keys.age\n{}\n",
+            target.display()
+        );
+        atomic::write(backend.dir.join(REENCRYPT_JOURNAL), journal.as_bytes())?;
+        let backend = FileBackend::open(backend.dir)?;
+
+        assert!(!target.exists());
+        assert!(staged_path(&target).exists());
+        assert!(!backend.dir.join(REENCRYPT_JOURNAL).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_all_resumes_after_interruption() -> Result<(), Error> {
+        let key = Identity::generate();
+        let new_key = Identity::generate();
+        let backend = temp_backend("passage-replace-resume");
+
+        let a: ItemName = "a".parse()?;
+        let b: ItemName = "dir/b".parse()?;
+        backend.create(
+            &a,
+            &crypt::encrypt_with_keys(b"secret a", &[key.to_public()])?,
+        )?;
+        backend.create(
+            &b,
+            &crypt::encrypt_with_keys(b"secret b", &[key.to_public()])?,
+        )?;
+
+        // Simulate a crash after the journal was written and one item was replaced
+        let encrypted = crypt::encrypt_with_keys(b"secret b", &[new_key.to_public()])?;
+        atomic::write(staged_path(&backend.path(&b)?), &encrypted)?;
+        atomic::write(backend.dir.join(REENCRYPT_JOURNAL), b"a.age\ndir/b.age\n")?;
+
+        let backend = FileBackend::open(backend.dir)?;
+
+        assert_eq!(
+            *crypt::decrypt_with_key(&backend.read(&b)?, &new_key)?,
+            b"secret b"
+        );
+        assert_eq!(
+            *crypt::decrypt_with_key(&backend.read(&a)?, &key)?,
+            b"secret a"
+        );
+        assert!(!backend.dir.join(REENCRYPT_JOURNAL).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_items() -> Result<(), Error> {
+        let backend = temp_backend("passage-streaming");
+        let name: ItemName = "dir/item".parse()?;
+
+        let mut writer = backend.writer(&name, false)?;
+        writer.write_all(b"first")?;
+        // Nothing is visible until the writer is committed
+        assert!(!backend.exists(&name)?);
+        writer.commit()?;
+
+        let mut writer = backend.writer(&name, false)?;
+        writer.write_all(b"again")?;
+        assert!(matches!(writer.commit(), Err(Error::ItemAlreadyExists(_))));
+
+        let mut writer = backend.writer(&name, true)?;
+        writer.write_all(b"second")?;
+        writer.commit()?;
+
+        let mut contents = vec![];
+        backend.reader(&name)?.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"second");
+        assert_eq!(backend.list()?, vec![name]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_outside_store_are_rejected() -> Result<(), Error> {
+        let backend = temp_backend("passage-symlink");
+        let outside = env::temp_dir().join("passage-symlink-outside");
+        fs::create_dir_all(&outside)?;
+        std::os::unix::fs::symlink(&outside, backend.dir.join("escape"))?;
+
+        let name = "escape/item".parse()?;
+        assert!(matches!(
+            backend.create(&name, b"secret"),
+            Err(Error::InvalidItemName(..))
+        ));
+        assert!(!outside.join("item.age").exists());
+
+        Ok(())
+    }
+}
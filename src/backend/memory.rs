@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use super::StoreBackend;
+use crate::error::Error;
+use crate::item::ItemName;
+
+/// A backend keeping everything in memory, for tests and for embedding passage in applications
+/// that persist the store by other means.
+#[derive(Default)]
+pub struct MemoryBackend {
+    items: RwLock<BTreeMap<ItemName, Vec<u8>>>,
+    meta: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty backend.
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+}
+
+impl StoreBackend for MemoryBackend {
+    fn list(&self) -> Result<Vec<ItemName>, Error> {
+        Ok(self.items.read().unwrap().keys().cloned().collect())
+    }
+
+    fn exists(&self, name: &ItemName) -> Result<bool, Error> {
+        Ok(self.items.read().unwrap().contains_key(name))
+    }
+
+    fn read(&self, name: &ItemName) -> Result<Vec<u8>, Error> {
+        self.items
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::ItemNotFound(name.to_string()))
+    }
+
+    fn create(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        let mut items = self.items.write().unwrap();
+        if items.contains_key(name) {
+            return Err(Error::ItemAlreadyExists(name.to_string()));
+        }
+
+        items.insert(name.clone(), data.to_vec());
+        Ok(())
+    }
+
+    fn write(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        self.items
+            .write()
+            .unwrap()
+            .insert(name.clone(), data.to_vec());
+        Ok(())
+    }
+
+    fn replace_all(&self, items: &[(ItemName, Vec<u8>)]) -> Result<(), Error> {
+        self.items.write().unwrap().extend(items.iter().cloned());
+        Ok(())
+    }
+
+    fn delete(&self, name: &ItemName) -> Result<(), Error> {
+        match self.items.write().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(Error::ItemNotFound(name.to_string())),
+        }
+    }
+
+    fn rename(&self, from: &ItemName, to: &ItemName) -> Result<(), Error> {
+        let mut items = self.items.write().unwrap();
+        if items.contains_key(to) {
+            return Err(Error::ItemAlreadyExists(to.to_string()));
+        }
+
+        let data = items
+            .remove(from)
+            .ok_or_else(|| Error::ItemNotFound(from.to_string()))?;
+        items.insert(to.clone(), data);
+        Ok(())
+    }
+
+    fn read_meta(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.meta.read().unwrap().get(key).cloned())
+    }
+
+    fn write_meta(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.meta
+            .write()
+            .unwrap()
+            .insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{BufferedWriter, ItemWriter, Replacement, StoreBackend};
+use crate::error::Error;
+use crate::item::ItemName;
+use crate::perms;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS items (
+    name TEXT PRIMARY KEY NOT NULL,
+    ciphertext BLOB NOT NULL,
+    created INTEGER NOT NULL,
+    modified INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY NOT NULL,
+    value BLOB NOT NULL
+);
+";
+
+/// The table holding the items of a [`SqliteReplacement`] until it is committed. Temporary tables
+/// are private to the connection and never written to the database itself.
+const STAGED_SCHEMA: &str = "
+CREATE TEMP TABLE IF NOT EXISTS staged (
+    name TEXT PRIMARY KEY NOT NULL,
+    ciphertext BLOB NOT NULL
+);
+DELETE FROM staged;
+";
+
+/// Seconds since the Unix epoch, used for the creation and modification times of items.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// A backend keeping every item in a single SQLite database, along with the time each item was
+/// created and last modified.
+///
+/// Since all changes happen in SQLite transactions, the store is never left half-updated and is
+/// easy to copy or sync as a single file.
+pub struct SqliteBackend {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// Open the database at `path`, creating it if it does not exist yet.
+    pub fn open(path: PathBuf) -> Result<SqliteBackend, Error> {
+        if let Some(parent) = path.parent() {
+            perms::create_dir_all(parent)?;
+        }
+
+        // Create the file ourselves so that it is only readable by the current user. SQLite gives
+        // its journal files the same permissions.
+        if !path.exists() {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&path)?;
+        }
+
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(SqliteBackend {
+            path,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// The path of the database.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The time an item was created and last modified, in seconds since the Unix epoch.
+    pub fn timestamps(&self, name: &ItemName) -> Result<(i64, i64), Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT created, modified FROM items WHERE name = ?1",
+                params![name.as_str()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| Error::ItemNotFound(name.to_string()))
+    }
+}
+
+/// Items staged in a temporary table, see [`SqliteBackend::replacement`].
+struct SqliteReplacement<'a> {
+    backend: &'a SqliteBackend,
+}
+
+impl Replacement for SqliteReplacement<'_> {
+    fn writer(&self, name: &ItemName) -> Result<Box<dyn ItemWriter + '_>, Error> {
+        Ok(Box::new(BufferedWriter::new(name, move |name, data| {
+            self.backend.conn.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO staged (name, ciphertext) VALUES (?1, ?2)",
+                params![name.as_str(), data],
+            )?;
+            Ok(())
+        })))
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), Error> {
+        let mut conn = self.backend.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO items (name, ciphertext, created, modified)
+             SELECT name, ciphertext, ?1, ?1 FROM staged WHERE true
+             ON CONFLICT (name) DO UPDATE SET ciphertext = excluded.ciphertext, modified = ?1",
+            params![now()],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+impl Drop for SqliteReplacement<'_> {
+    fn drop(&mut self) {
+        let _ = self
+            .backend
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM staged", []);
+    }
+}
+
+impl StoreBackend for SqliteBackend {
+    fn list(&self) -> Result<Vec<ItemName>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name FROM items")?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|name| ItemName::new(&name.ok()?).ok())
+            .collect();
+        Ok(names)
+    }
+
+    fn exists(&self, name: &ItemName) -> Result<bool, Error> {
+        let found = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT 1 FROM items WHERE name = ?1",
+                params![name.as_str()],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn read(&self, name: &ItemName) -> Result<Vec<u8>, Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT ciphertext FROM items WHERE name = ?1",
+                params![name.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::ItemNotFound(name.to_string()))
+    }
+
+    fn create(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        let now = now();
+        let inserted = self.conn.lock().unwrap().execute(
+            "INSERT INTO items (name, ciphertext, created, modified) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (name) DO NOTHING",
+            params![name.as_str(), data, now],
+        )?;
+
+        if inserted == 0 {
+            return Err(Error::ItemAlreadyExists(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, name: &ItemName, data: &[u8]) -> Result<(), Error> {
+        let now = now();
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO items (name, ciphertext, created, modified) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (name) DO UPDATE SET ciphertext = ?2, modified = ?3",
+            params![name.as_str(), data, now],
+        )?;
+
+        Ok(())
+    }
+
+    fn replace_all(&self, items: &[(ItemName, Vec<u8>)]) -> Result<(), Error> {
+        let now = now();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (name, data) in items {
+            tx.execute(
+                "INSERT INTO items (name, ciphertext, created, modified) VALUES (?1, ?2, ?3, ?3)
+                 ON CONFLICT (name) DO UPDATE SET ciphertext = ?2, modified = ?3",
+                params![name.as_str(), data, now],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// New contents are staged in a temporary table and copied to the items in one transaction.
+    fn replacement(&self) -> Result<Box<dyn Replacement + '_>, Error> {
+        self.conn.lock().unwrap().execute_batch(STAGED_SCHEMA)?;
+        Ok(Box::new(SqliteReplacement { backend: self }))
+    }
+
+    fn delete(&self, name: &ItemName) -> Result<(), Error> {
+        let deleted = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM items WHERE name = ?1", params![name.as_str()])?;
+
+        if deleted == 0 {
+            return Err(Error::ItemNotFound(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn rename(&self, from: &ItemName, to: &ItemName) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let exists = |name: &ItemName| {
+            tx.query_row(
+                "SELECT 1 FROM items WHERE name = ?1",
+                params![name.as_str()],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|found| found.is_some())
+        };
+        if !exists(from)? {
+            return Err(Error::ItemNotFound(from.to_string()));
+        }
+        if exists(to)? {
+            return Err(Error::ItemAlreadyExists(to.to_string()));
+        }
+
+        tx.execute(
+            "UPDATE items SET name = ?2, modified = ?3 WHERE name = ?1",
+            params![from.as_str(), to.as_str(), now()],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn read_meta(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let value = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    }
+
+    fn write_meta(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = ?2",
+            params![key, data],
+        )?;
+
+        Ok(())
+    }
+
+    fn check_permissions(&self) -> Vec<String> {
+        let mut paths = vec![self.path.clone()];
+        if let Some(parent) = self.path.parent() {
+            paths.insert(0, parent.to_path_buf());
+        }
+
+        paths.iter().filter_map(perms::check).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    fn temp_backend(name: &str) -> SqliteBackend {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+
+        SqliteBackend::open(dir.join("passage.db")).unwrap()
+    }
+
+    #[test]
+    fn items_and_meta_round_trip() -> Result<(), Error> {
+        let backend = temp_backend("passage-sqlite-round-trip");
+        let a: ItemName = "dir/a".parse()?;
+        let b: ItemName = "b".parse()?;
+
+        backend.create(&a, b"first")?;
+        assert!(matches!(
+            backend.create(&a, b"again"),
+            Err(Error::ItemAlreadyExists(_))
+        ));
+        backend.write(&a, b"second")?;
+        assert_eq!(backend.read(&a)?, b"second");
+
+        let (created, modified) = backend.timestamps(&a)?;
+        assert!(created > 0 && modified >= created);
+
+        backend.rename(&a, &b)?;
+        assert!(!backend.exists(&a)?);
+        assert_eq!(backend.list()?, vec![b.clone()]);
+        backend.delete(&b)?;
+        assert!(matches!(backend.read(&b), Err(Error::ItemNotFound(_))));
+
+        assert_eq!(backend.read_meta("public-keys")?, None);
+        backend.write_meta("public-keys", b"age1...\n")?;
+        assert_eq!(
+            backend.read_meta("public-keys")?,
+            Some(b"age1...\n".to_vec())
+        );
+
+        // Everything survives reopening the database
+        backend.create(&a, b"persisted")?;
+        let reopened = SqliteBackend::open(backend.path().to_path_buf())?;
+        assert_eq!(reopened.read(&a)?, b"persisted");
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_all_is_atomic() -> Result<(), Error> {
+        let backend = temp_backend("passage-sqlite-replace");
+        let a: ItemName = "a".parse()?;
+        backend.create(&a, b"old a")?;
+
+        // Make the second insert fail partway through the transaction
+        backend.conn.lock().unwrap().execute_batch(
+            "CREATE TRIGGER fail BEFORE INSERT ON items WHEN NEW.name = 'b'
+             BEGIN SELECT RAISE(ABORT, 'no b'); END;",
+        )?;
+
+        let items = vec![
+            (a.clone(), b"new a".to_vec()),
+            ("b".parse()?, b"new b".to_vec()),
+        ];
+        assert!(backend.replace_all(&items).is_err());
+        assert_eq!(backend.read(&a)?, b"old a");
+
+        // Items staged one at a time are replaced together
+        let replacement = backend.replacement()?;
+        for (name, data) in &items {
+            let mut writer = replacement.writer(name)?;
+            writer.write_all(data)?;
+            writer.commit()?;
+        }
+        assert!(replacement.commit().is_err());
+        assert_eq!(backend.read(&a)?, b"old a");
+
+        backend
+            .conn
+            .lock()
+            .unwrap()
+            .execute_batch("DROP TRIGGER fail")?;
+        let replacement = backend.replacement()?;
+        for (name, data) in &items {
+            let mut writer = replacement.writer(name)?;
+            writer.write_all(data)?;
+            writer.commit()?;
+        }
+        replacement.commit()?;
+        assert_eq!(backend.read(&a)?, b"new a");
+        assert_eq!(backend.read(&"b".parse()?)?, b"new b");
+
+        Ok(())
+    }
+}
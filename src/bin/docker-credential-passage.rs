@@ -0,0 +1,37 @@
+//! The docker credential helper of passage.
+//!
+//! Docker runs `docker-credential-passage OPERATION` for registries configured to use the
+//! `passage` credential store. This runs `passage docker-credential OPERATION`, preferring the
+//! passage program installed next to this one, so that passage reads its configuration and secret
+//! key as usual.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+fn main() {
+    let name = format!("passage{}", env::consts::EXE_SUFFIX);
+    let passage = env::current_exe()
+        .map(|exe| exe.with_file_name(&name))
+        .ok()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(&name));
+
+    let mut command = Command::new(&passage);
+    command
+        .arg("docker-credential")
+        .args(env::args_os().skip(1));
+
+    #[cfg(unix)]
+    let error = std::os::unix::process::CommandExt::exec(&mut command);
+
+    #[cfg(not(unix))]
+    let error = match command.status() {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    };
+
+    // Docker shows what helpers print to standard output
+    println!("could not run {}: {}", passage.display(), error);
+    process::exit(1);
+}
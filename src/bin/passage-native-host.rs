@@ -0,0 +1,33 @@
+//! The native messaging host of passage, through which browser extensions read the store.
+//!
+//! Browsers start this program with arguments describing the extension, which are ignored, and
+//! exchange messages with it on standard input and output. It runs `passage native-host`,
+//! preferring the passage program installed next to this one.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+fn main() {
+    let name = format!("passage{}", env::consts::EXE_SUFFIX);
+    let passage = env::current_exe()
+        .map(|exe| exe.with_file_name(&name))
+        .ok()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(&name));
+
+    let mut command = Command::new(&passage);
+    command.arg("native-host");
+
+    #[cfg(unix)]
+    let error = std::os::unix::process::CommandExt::exec(&mut command);
+
+    #[cfg(not(unix))]
+    let error = match command.status() {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    };
+
+    eprintln!("could not run {}: {}", passage.display(), error);
+    process::exit(1);
+}
@@ -0,0 +1,123 @@
+//! Copying passwords to the clipboard.
+
+use clipboard::{ClipboardContext, ClipboardProvider};
+use std::thread;
+use std::time::Duration;
+
+use passage::Error;
+
+/// The hidden subcommand of the process holding the clipboard, which runs [`serve`] on Unix.
+pub const DAEMON: &str = "clipboard-daemon";
+
+/// Wait `timeout` seconds, then clear the clipboard unless something else was copied meanwhile.
+fn clear_later(ctx: &mut ClipboardContext, text: &str, timeout: u64) {
+    thread::sleep(Duration::from_secs(timeout));
+    if ctx.get_contents().ok().as_deref() == Some(text) {
+        let _ = ctx.set_contents(String::new());
+    }
+}
+
+/// Copy `text` to the clipboard and clear it again after `timeout` seconds, or never if `timeout`
+/// is 0.
+///
+/// On Unix the clipboard is cleared by a background process, which also keeps serving the
+/// clipboard on systems where it is only available while the program that set it is running.
+pub fn copy(text: &str, timeout: u64) -> Result<(), Error> {
+    if timeout == 0 {
+        let mut ctx: ClipboardContext = ClipboardProvider::new()?;
+        return Ok(ctx.set_contents(text.to_string())?);
+    }
+
+    #[cfg(unix)]
+    return background::copy(text, timeout);
+
+    #[cfg(not(unix))]
+    {
+        let mut ctx: ClipboardContext = ClipboardProvider::new()?;
+        ctx.set_contents(text.to_string())?;
+        eprintln!("Clearing the clipboard in {} seconds.", timeout);
+        clear_later(&mut ctx, text, timeout);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod background {
+    use clipboard::{ClipboardContext, ClipboardProvider};
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::io::{self, prelude::*};
+    use std::os::unix::io::AsRawFd;
+    use std::process::{Command, Stdio};
+    use zeroize::Zeroizing;
+
+    use passage::Error;
+
+    /// Detach the current process from the terminal and from whoever reads its output.
+    fn detach() {
+        // SAFETY: setsid and dup2 only affect the current process' session and file descriptors
+        unsafe {
+            libc::setsid();
+            if let Ok(null) = OpenOptions::new().read(true).write(true).open("/dev/null") {
+                for fd in 0..=2 {
+                    libc::dup2(null.as_raw_fd(), fd);
+                }
+            }
+        }
+    }
+
+    /// Copy `text` in a new passage process that clears the clipboard after `timeout` seconds.
+    /// The text is sent to the process on its standard input, and it reports on its standard
+    /// output whether copying worked.
+    pub fn copy(text: &str, timeout: u64) -> Result<(), Error> {
+        let mut child = Command::new(env::current_exe()?)
+            .arg(super::DAEMON)
+            .arg(timeout.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // Closing standard input tells the process that the text is complete
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+        let mut report = String::new();
+        child.stdout.take().unwrap().read_to_string(&mut report)?;
+
+        match report.trim_end() {
+            "ok" => Ok(()),
+            "" => Err(Error::Other(
+                "The process holding the clipboard exited unexpectedly.".into(),
+            )),
+            message => Err(Error::Other(message.to_string())),
+        }
+    }
+
+    /// Copy the text read from standard input to the clipboard, report whether that worked, then
+    /// keep serving the clipboard until it is cleared after `timeout` seconds.
+    pub fn serve(timeout: u64) {
+        let mut text = Zeroizing::new(String::new());
+        let result = io::stdin()
+            .read_to_string(&mut text)
+            .map_err(Error::from)
+            .and_then(|_| {
+                let mut ctx: ClipboardContext = ClipboardProvider::new()?;
+                ctx.set_contents(text.to_string())?;
+                Ok(ctx)
+            });
+
+        let report = match &result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let mut stdout = io::stdout();
+        let _ = writeln!(stdout, "{}", report).and_then(|()| stdout.flush());
+        detach();
+
+        if let Ok(mut ctx) = result {
+            super::clear_later(&mut ctx, &text, timeout);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use background::serve;
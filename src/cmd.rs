@@ -1,17 +1,294 @@
+mod docker_credential;
 mod edit;
+mod exec;
+mod find;
+mod generate;
+mod git_credential;
 mod init;
+mod inject;
 mod insert;
 mod key;
 mod list;
 mod lock;
+mod native_host;
+mod pick;
 mod remove;
 mod show;
+mod stores;
+mod verify;
 
+pub use docker_credential::docker_credential;
 pub use edit::edit;
+pub use exec::exec;
+pub use find::find;
+pub use generate::generate;
+pub use git_credential::git_credential;
 pub use init::init;
+pub use inject::inject;
 pub use insert::insert;
 pub use key::key;
 pub use list::list;
-pub use lock::{lock, unlock};
+pub use lock::{lock, passwd, unlock};
+pub use native_host::native_host;
+pub use pick::pick;
 pub use remove::remove;
 pub use show::show;
+pub use stores::{add_store, list_stores, remove_store};
+pub use verify::verify;
+
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use zeroize::Zeroizing;
+
+use passage::{manifest, Error, Identity, ItemName, PasswordStore, Prompt};
+
+use crate::config::Config;
+
+/// Read the secret key for `store` from whichever identity file holds it.
+fn secret_key(
+    store: &PasswordStore,
+    config: &Config,
+    prompt: &dyn Prompt,
+) -> Result<Identity, Error> {
+    config.identity()?;
+    passage::key::find_secret_key(&config.identities, &store.recipients, prompt)
+}
+
+/// Read the secret key and unlock the store if it hides its item names or keeps a manifest, so
+/// that its items can be found by name and modified.
+fn unlock_store(store: &PasswordStore, config: &Config) -> Result<(), Error> {
+    if store.needs_unlock() {
+        let key = secret_key(store, config, &crate::input::prompt)?;
+        store.unlock(&key)?;
+    }
+
+    Ok(())
+}
+
+/// Read the secret key for `store` and unlock the store with it if needed, so that the key is
+/// only asked for once.
+fn open_with_key(store: &PasswordStore, config: &Config) -> Result<Identity, Error> {
+    let key = secret_key(store, config, &crate::input::prompt)?;
+    if store.needs_unlock() {
+        store.unlock(&key)?;
+    }
+
+    Ok(key)
+}
+
+/// Describe the fields of `item` that are not secret, for the picker.
+fn preview(store: &PasswordStore, key: Option<&Identity>, item: &ItemName) -> Vec<String> {
+    let key = match key {
+        Some(key) => key,
+        None => return vec!["(no preview while the secret key is locked)".into()],
+    };
+    let entry = match store.get_entry(item, key) {
+        Ok(entry) => entry,
+        Err(e) => return vec![format!("(could not decrypt: {})", e)],
+    };
+
+    let mut lines = vec![];
+    if let Some(username) = &entry.username {
+        lines.push(format!("username: {}", username));
+    }
+    if let Some(url) = &entry.url {
+        lines.push(format!("url: {}", url));
+    }
+    if !entry.tags.is_empty() {
+        lines.push(format!("tags: {}", entry.tags.join(", ")));
+    }
+    // Other fields may be secret, so only their names are shown
+    if !entry.fields.is_empty() {
+        let names: Vec<&str> = entry.fields.iter().map(|(key, _)| key.as_str()).collect();
+        lines.push(format!("fields: {}", names.join(", ")));
+    }
+    lines
+}
+
+/// Ask the user for the name of an item with the picker, or with `prompt` if they are not at a
+/// terminal. Returns `None` if the user cancels. With `new`, the user can name a new item.
+///
+/// Items are only previewed if the secret key can be read without asking for its passphrase.
+fn choose(
+    store: &PasswordStore,
+    config: &Config,
+    prompt: &str,
+    new: bool,
+) -> Result<Option<String>, Error> {
+    if !crate::pick::available() {
+        return crate::input::read_input(prompt).map(Some);
+    }
+
+    let key = if store.needs_unlock() {
+        Some(open_with_key(store, config)?)
+    } else {
+        secret_key(store, config, &|_: &str| Err(Error::KeyEncrypted)).ok()
+    };
+    let items = store.list()?;
+    crate::pick::pick(&items, new, |item| preview(store, key.as_ref(), item))
+}
+
+/// Split a reference to an item, `ITEM`, or to one of its fields, `ITEM:FIELD`. A reference
+/// containing a colon is taken to be an item name if that item exists.
+fn reference(store: &PasswordStore, reference: &str) -> Result<(ItemName, Option<String>), Error> {
+    if let Ok(item) = reference.parse() {
+        if store.exists(&item) {
+            return Ok((item, None));
+        }
+    }
+
+    match reference.rsplit_once(':') {
+        Some((item, field)) if !field.is_empty() => Ok((item.parse()?, Some(field.to_string()))),
+        _ => Err(Error::ItemNotFound(reference.to_string())),
+    }
+}
+
+/// Decrypt the value of `field` of `item`, or its password if no field is given.
+fn lookup(
+    store: &PasswordStore,
+    key: &Identity,
+    item: &ItemName,
+    field: Option<&str>,
+) -> Result<Zeroizing<String>, Error> {
+    let field = field.unwrap_or("password");
+    match store.get_entry(item, key)?.get(field) {
+        Some(value) => Ok(Zeroizing::new(value)),
+        None => Err(Error::Other(format!("{} has no field {:?}.", item, field))),
+    }
+}
+
+/// Tell the user that the password for `item` was copied to the clipboard.
+fn copied(item: &ItemName, config: &Config) {
+    if config.clip_timeout > 0 {
+        eprintln!(
+            "Copied password for {} to clipboard. Will clear in {} seconds.",
+            item, config.clip_timeout
+        );
+    } else {
+        eprintln!("Copied password for {} to clipboard.", item);
+    }
+}
+
+/// Commit every change to the store if it is a git repository and auto-commit is enabled.
+///
+/// Failing to commit only prints a warning, since the change itself was made.
+fn git_commit(store: &PasswordStore, config: &Config, message: &str) {
+    if !config.git_auto_commit || !config.store_dir.join(".git").exists() {
+        return;
+    }
+
+    // Commit messages would give away the names of the items
+    let message = if store.hides_names() {
+        "Update password store"
+    } else {
+        message
+    };
+
+    match commit_all(&config.store_dir, message) {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Warning: git commit failed ({})", status),
+        Err(e) => eprintln!("Warning: could not run git: {}", e),
+    }
+}
+
+/// Commit every change in the git repository in `dir`.
+fn commit_all(dir: &Path, message: &str) -> io::Result<ExitStatus> {
+    let git = |args: &[&str]| Command::new("git").arg("-C").arg(dir).args(args).status();
+    git(&["add", "--all"]).and_then(|_| git(&["commit", "--quiet", "-m", message]))
+}
+
+/// The local identity of the store, which its manifest is pinned to.
+fn store_identity(config: &Config) -> String {
+    config
+        .store_dir
+        .canonicalize()
+        .unwrap_or_else(|_| config.store_dir.clone())
+        .display()
+        .to_string()
+}
+
+/// Describe how the store was rolled back, if its manifest is older than one seen before.
+///
+/// Fails with [`Error::UntrustedManifest`] if the manifest was replaced. Without a data directory
+/// to record the manifests seen in, this cannot be detected, so only a warning is printed.
+fn rollback(store: &PasswordStore, config: &Config) -> Result<Option<String>, Error> {
+    let manifest = match store.manifest()? {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+    match manifest::versions_path() {
+        Ok(path) => manifest::check_version(&path, &store_identity(config), &manifest),
+        Err(Error::NoDataDir) => {
+            eprintln!(
+                "Warning: no data directory found, so rollbacks of the store cannot be detected."
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Fail if the store was rolled back or its manifest replaced, so that nothing read from it is
+/// used.
+fn check_rollback(store: &PasswordStore, config: &Config) -> Result<(), Error> {
+    match rollback(store, config)? {
+        Some(problem) => Err(Error::Other(format!(
+            "{}. Run \"passage verify\" for details.",
+            problem
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Check the store against its manifest, describing every problem found.
+fn check_manifest(store: &PasswordStore, config: &Config) -> Result<Vec<String>, Error> {
+    let mut problems = store.verify()?;
+    problems.extend(rollback(store, config)?);
+    Ok(problems)
+}
+
+/// Accept the store's current manifest as the one later manifests are compared to.
+fn trust_manifest(store: &PasswordStore, config: &Config) -> Result<(), Error> {
+    let manifest = match store.manifest()? {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+    match manifest::versions_path() {
+        Ok(path) => manifest::trust(&path, &store_identity(config), &manifest),
+        Err(Error::NoDataDir) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn committing_every_change() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+        };
+        git(&["init", "--quiet"])?;
+        git(&["config", "user.name", "passage"])?;
+        git(&["config", "user.email", "passage@example.com"])?;
+
+        fs::write(dir.path().join("item.age"), "ciphertext")?;
+        assert!(commit_all(dir.path(), "Add item.")?.success());
+
+        let log = git(&["log", "--format=%s", "--name-only"])?;
+        assert_eq!(
+            String::from_utf8_lossy(&log.stdout),
+            "Add item.\n\nitem.age\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, prelude::*};
+use zeroize::Zeroizing;
+
+use passage::{Entry, Error, ItemName, PasswordStore};
+
+use crate::config::Config;
+
+/// The message docker expects when there are no credentials for a registry.
+const NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Credentials as exchanged with docker.
+#[derive(Deserialize, Serialize)]
+struct Credentials<S> {
+    #[serde(rename = "ServerURL")]
+    server_url: S,
+    #[serde(rename = "Username")]
+    username: S,
+    #[serde(rename = "Secret")]
+    secret: S,
+}
+
+/// The item under `prefix` holding the credentials for `server_url`, named after the registry
+/// without its scheme or trailing slashes.
+fn item_name(prefix: &str, server_url: &str) -> Result<ItemName, Error> {
+    let server = server_url
+        .split_once("://")
+        .map_or(server_url, |(_, rest)| rest)
+        .trim_end_matches('/');
+    format!("{}/{}", prefix, server).parse()
+}
+
+fn read_input() -> Result<Zeroizing<String>, Error> {
+    let mut input = Zeroizing::new(String::new());
+    io::stdin().read_to_string(&mut input)?;
+    Ok(input)
+}
+
+fn get(store: &PasswordStore, config: &Config, server_url: &str) -> Result<(), Error> {
+    let key = super::open_with_key(store, config)?;
+    let item = item_name(&config.docker_prefix, server_url)?;
+    if !store.exists(&item) {
+        println!("{}", NOT_FOUND);
+        return Err(Error::ItemNotFound(item.to_string()));
+    }
+
+    let entry = store.get_entry(&item, &key)?;
+    super::check_rollback(store, config)?;
+
+    let credentials = Credentials {
+        server_url: entry.url.as_deref().unwrap_or(server_url),
+        username: entry.username.as_deref().unwrap_or_default(),
+        secret: entry.password.as_str(),
+    };
+    let output = Zeroizing::new(serde_json::to_string(&credentials)?);
+    println!("{}", *output);
+    Ok(())
+}
+
+fn store_credentials(store: &PasswordStore, config: &Config, input: &str) -> Result<(), Error> {
+    let credentials: Credentials<String> = serde_json::from_str(input)?;
+    let secret = Zeroizing::new(credentials.secret);
+    let item = item_name(&config.docker_prefix, &credentials.server_url)?;
+
+    let mut entry = Entry::new(&secret);
+    entry.username = Some(credentials.username);
+    entry.url = Some(credentials.server_url);
+
+    super::unlock_store(store, config)?;
+    let text = Zeroizing::new(entry.to_string());
+    let action = if store.exists(&item) {
+        store.update(&item, &text)?;
+        "Update"
+    } else {
+        store.insert(&item, &text)?;
+        "Add"
+    };
+    super::git_commit(
+        store,
+        config,
+        &format!("{} docker credentials {}.", action, item),
+    );
+    Ok(())
+}
+
+fn erase(store: &PasswordStore, config: &Config, server_url: &str) -> Result<(), Error> {
+    super::unlock_store(store, config)?;
+    let item = item_name(&config.docker_prefix, server_url)?;
+    if !store.exists(&item) {
+        println!("{}", NOT_FOUND);
+        return Err(Error::ItemNotFound(item.to_string()));
+    }
+
+    store.delete(&item)?;
+    super::git_commit(
+        store,
+        config,
+        &format!("Remove docker credentials {}.", item),
+    );
+    Ok(())
+}
+
+fn list(store: &PasswordStore, config: &Config) -> Result<(), Error> {
+    let key = super::open_with_key(store, config)?;
+    let prefix = format!("{}/", config.docker_prefix);
+
+    let mut registries = BTreeMap::new();
+    for item in store.list()? {
+        if let Some(server) = item.as_str().strip_prefix(&prefix) {
+            let entry = store.get_entry(&item, &key)?;
+            let server_url = entry.url.clone().unwrap_or_else(|| server.to_string());
+            registries.insert(server_url, entry.username.clone().unwrap_or_default());
+        }
+    }
+
+    println!("{}", serde_json::to_string(&registries)?);
+    Ok(())
+}
+
+/// Act as a docker credential helper: read the server URL or, for `store`, the credentials from
+/// standard input and get, store, erase or list credentials as docker expects.
+pub fn docker_credential(
+    store: PasswordStore,
+    config: &Config,
+    operation: &str,
+) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    match operation {
+        "get" => get(&store, config, read_input()?.trim()),
+        "store" => store_credentials(&store, config, &read_input()?),
+        "erase" => erase(&store, config, read_input()?.trim()),
+        "list" => list(&store, config),
+        "version" => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        _ => Err(Error::Other(format!("Unknown operation {:?}.", operation))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn naming_registries() -> Result<(), Error> {
+        for url in [
+            "https://index.docker.io/v1/",
+            "index.docker.io/v1",
+            "http://index.docker.io/v1//",
+        ] {
+            assert_eq!(
+                item_name("registries", url)?.as_str(),
+                "registries/index.docker.io/v1"
+            );
+        }
+        assert_eq!(
+            item_name("registries", "localhost:5000")?.as_str(),
+            "registries/localhost:5000"
+        );
+
+        Ok(())
+    }
+}
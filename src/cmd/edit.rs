@@ -1,9 +1,16 @@
-use crate::error::Error;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::Command;
+use zeroize::Zeroizing;
+
+use passage::{Error, ExposeSecret, ItemName, PasswordStore};
+
+use crate::config::Config;
 use crate::input;
-use crate::store::PasswordStore;
 
 fn distance(a: &str, b: &str) -> u32 {
-    let mut distance = (a.len() as isize - b.len() as isize).abs() as u32;
+    let mut distance = a.len().abs_diff(b.len()) as u32;
     for (i, j) in a.chars().zip(b.chars()) {
         if i != j {
             distance += 1;
@@ -13,30 +20,115 @@ fn distance(a: &str, b: &str) -> u32 {
     distance
 }
 
-pub fn edit(store: PasswordStore, item: Option<&str>) -> Result<(), Error> {
+/// Let the user change `text` in `editor`, returning the result.
+///
+/// The text is written to a file readable only by the current user, in memory-backed `/dev/shm`
+/// where available, and overwritten before the file is removed.
+fn edit_text(editor: &str, text: &str) -> Result<Zeroizing<String>, Error> {
+    let shm = Path::new("/dev/shm");
+    let dir = if shm.is_dir() {
+        shm.to_path_buf()
+    } else {
+        std::env::temp_dir()
+    };
+    let mut file = tempfile::Builder::new()
+        .prefix("passage.")
+        .suffix(".txt")
+        .tempfile_in(dir)?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+
+    let mut words = editor.split_whitespace();
+    let status = Command::new(words.next().unwrap_or_default())
+        .args(words)
+        .arg(file.path())
+        .status();
+    let edited = fs::read_to_string(file.path()).map(Zeroizing::new);
+
+    // Overwrite the file before it is removed. Editors may have replaced it with a new file, so
+    // it is opened again by name
+    if let Ok(mut scrub) = fs::OpenOptions::new().write(true).open(file.path()) {
+        let len = scrub.metadata()?.len() as usize;
+        scrub.write_all(&vec![0; len])?;
+        scrub.sync_all()?;
+    }
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => return Err(Error::Other(format!("{} failed ({}).", editor, status))),
+        Err(e) => return Err(Error::Other(format!("Could not run {}: {}", editor, e))),
+    }
+
+    edited.map_err(|e| Error::Other(format!("Could not read edited item: {}", e)))
+}
+
+/// Open the decrypted item in `editor` and save the result.
+fn edit_with(
+    store: &PasswordStore,
+    config: &Config,
+    item: &ItemName,
+    editor: &str,
+) -> Result<(), Error> {
+    let key = super::secret_key(store, config, &input::prompt)?;
+    let secret = store.get(item, &key)?;
+
+    let edited = edit_text(editor, secret.expose_secret())?;
+    if edited.as_str() == secret.expose_secret() {
+        eprintln!("{} is unchanged.", item);
+        return Ok(());
+    }
+
+    store.update(item, &edited)?;
+    super::git_commit(
+        store,
+        config,
+        &format!("Edit password for {} using {}.", item, editor),
+    );
+    eprintln!("Updated entry in the password store for {}.", item);
+    Ok(())
+}
+
+pub fn edit(store: PasswordStore, config: &Config, item: Option<&str>) -> Result<(), Error> {
     let item = match item {
         Some(s) => s.to_string(),
-        None => {
-            input::read_input("Enter the name of the item in your store you wish to modify.\n>")?
-        }
+        None => match super::choose(
+            &store,
+            config,
+            "Enter the name of the item in your store you wish to modify.\n>",
+            false,
+        )? {
+            Some(item) => item,
+            None => return Ok(()),
+        },
     };
+    let item: ItemName = item.parse()?;
+
+    super::unlock_store(&store, config)?;
 
     if !store.exists(&item) {
-        match store.list()?.iter().map(|e| (e, distance(&item, &e))).min_by_key(|e| e.1) {
-            Some((closest, distance)) => {
-                if distance < 3 {
-                    let prompt = format!("{} not found in the password store. Did you mean {} [Y/n]?", item, closest);
-                    match input::read_input(&prompt) {
-                        Ok(s) => if s.to_ascii_lowercase() == "y" {
-                            return edit(store, Some(&s));
-                        },
-                        _ => {},
-                    };
+        if let Some((closest, distance)) = store
+            .list()?
+            .iter()
+            .map(|e| (e, distance(item.as_str(), e.as_str())))
+            .min_by_key(|e| e.1)
+        {
+            if distance < 3 {
+                let prompt = format!(
+                    "{} not found in the password store. Did you mean {} [Y/n]?",
+                    item, closest
+                );
+                if let Ok(s) = input::read_input(&prompt) {
+                    if s.is_empty() || s.eq_ignore_ascii_case("y") {
+                        return edit(store, config, Some(closest.as_str()));
+                    }
                 }
-            },
-            _ => {},
+            }
         }
-        return Err(Error::ItemNotFound(item));
+        return Err(Error::ItemNotFound(item.to_string()));
+    }
+
+    if let Some(editor) = &config.editor {
+        return edit_with(&store, config, &item, editor);
     }
 
     let password = input::read_secret(
@@ -44,7 +136,22 @@ pub fn edit(store: PasswordStore, item: Option<&str>) -> Result<(), Error> {
         Some(&format!("Retype new password for {}", item)),
     )?;
 
-    store.update(&item, &password)?;
+    store.update(&item, password.expose_secret())?;
+    super::git_commit(&store, config, &format!("Edit password for {}.", item));
     eprintln!("Updated entry in the password store for {}.", item);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editing_text() -> Result<(), Error> {
+        let edited = edit_text("sed -i s/hunter2/correct-horse/", "hunter2\nuser: me\n")?;
+        assert_eq!(edited.as_str(), "correct-horse\nuser: me\n");
+        assert!(edit_text("false", "hunter2").is_err());
+
+        Ok(())
+    }
+}
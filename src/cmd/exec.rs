@@ -0,0 +1,63 @@
+use std::process::Command;
+use zeroize::Zeroizing;
+
+use passage::{Error, PasswordStore};
+
+use crate::config::Config;
+
+/// Run `command` with the items named by `vars`, each `VAR=ITEM` or `VAR=ITEM:FIELD`, in its
+/// environment.
+///
+/// On Unix passage replaces itself with the command, so that the decrypted items only ever live
+/// in the command's environment.
+pub fn exec(
+    store: PasswordStore,
+    config: &Config,
+    vars: &[&str],
+    command: &[&str],
+) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    let key = super::open_with_key(&store, config)?;
+
+    let mut env: Vec<(&str, Zeroizing<String>)> = vec![];
+    for var in vars {
+        let (name, reference) = match var.split_once('=') {
+            Some((name, reference)) if !name.is_empty() && !reference.is_empty() => {
+                (name, reference)
+            }
+            _ => {
+                return Err(Error::Other(format!(
+                    "Expected VAR=ITEM or VAR=ITEM:FIELD, not {:?}.",
+                    var
+                )))
+            }
+        };
+        let (item, field) = super::reference(&store, reference)?;
+        env.push((name, super::lookup(&store, &key, &item, field.as_deref())?));
+    }
+
+    super::check_rollback(&store, config)?;
+
+    let mut child = Command::new(command[0]);
+    child
+        .args(&command[1..])
+        .envs(env.iter().map(|(name, value)| (name, value.as_str())));
+
+    #[cfg(unix)]
+    {
+        let e = std::os::unix::process::CommandExt::exec(&mut child);
+        Err(Error::Other(format!("Could not run {}: {}", command[0], e)))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let status = child
+            .status()
+            .map_err(|e| Error::Other(format!("Could not run {}: {}", command[0], e)))?;
+        drop(env);
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
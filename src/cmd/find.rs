@@ -0,0 +1,22 @@
+use passage::{Error, PasswordStore};
+
+use crate::config::Config;
+
+pub fn find(store: PasswordStore, config: &Config, pattern: &str, json: bool) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    super::unlock_store(&store, config)?;
+    let items = store.find(pattern)?;
+    if json {
+        println!("{}", serde_json::to_string(&items)?);
+        return Ok(());
+    }
+
+    for name in items {
+        println!("{}", name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use rand::seq::SliceRandom;
+use zeroize::Zeroizing;
+
+use passage::{Error, ItemName, PasswordStore};
+
+use crate::config::Config;
+use crate::{clip, input};
+
+/// A random password of `length` characters from `charset`.
+fn password(length: usize, charset: &[char]) -> Zeroizing<String> {
+    let mut rng = rand::thread_rng();
+    let mut password = Zeroizing::new(String::with_capacity(length * 4));
+    for _ in 0..length {
+        password.push(*charset.choose(&mut rng).unwrap());
+    }
+    password
+}
+
+pub fn generate(
+    store: PasswordStore,
+    config: &Config,
+    item: &str,
+    length: Option<usize>,
+    copy_to_clipboard: bool,
+    force: bool,
+) -> Result<(), Error> {
+    let item: ItemName = item.parse()?;
+    super::unlock_store(&store, config)?;
+
+    let exists = store.exists(&item);
+    if exists && !force {
+        let ans = input::read_input(&format!(
+            "{} already exists in the password store. Overwrite? [y/N]",
+            item
+        ))?
+        .to_lowercase();
+        if ans != "y" && ans != "yes" {
+            return Ok(());
+        }
+    }
+
+    let password = password(length.unwrap_or(config.length), &config.charset);
+    if exists {
+        store.update(&item, &password)?;
+    } else {
+        store.insert(&item, &password)?;
+    }
+    super::git_commit(
+        &store,
+        config,
+        &format!("Add generated password for {} to store.", item),
+    );
+
+    if copy_to_clipboard {
+        clip::copy(&password, config.clip_timeout)?;
+        super::copied(&item, config);
+    } else {
+        eprintln!("The generated password for {} is:", item);
+        println!("{}", *password);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generating_passwords() {
+        let charset: Vec<char> = "ab€".chars().collect();
+        let password = password(40, &charset);
+        assert_eq!(password.chars().count(), 40);
+        assert!(password.chars().all(|c| charset.contains(&c)));
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::{self, prelude::*};
+use zeroize::Zeroizing;
+
+use passage::{Entry, Error, ItemName, PasswordStore};
+
+use crate::config::Config;
+
+/// The attributes of a credential that passage uses, as described by git.
+#[derive(Debug, Default)]
+struct Credential {
+    protocol: Option<String>,
+    host: Option<String>,
+    username: Option<String>,
+    password: Option<Zeroizing<String>>,
+}
+
+impl Credential {
+    /// Read `key=value` lines up to a blank line or the end of the input, ignoring attributes
+    /// passage does not use.
+    fn read<R: BufRead>(input: R) -> Result<Credential, Error> {
+        let mut credential = Credential::default();
+
+        for line in input.lines() {
+            let line = Zeroizing::new(line?);
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => continue,
+            };
+            match key {
+                "protocol" => credential.protocol = Some(value),
+                "host" => credential.host = Some(value),
+                "username" => credential.username = Some(value),
+                "password" => credential.password = Some(Zeroizing::new(value)),
+                _ => {}
+            }
+        }
+
+        Ok(credential)
+    }
+
+    /// The item holding the credential, `git/HOST/USERNAME`.
+    fn item(&self) -> Option<ItemName> {
+        match (&self.host, &self.username) {
+            (Some(host), Some(username)) => format!("git/{}/{}", host, username).parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Find the item for `credential`. Without a username, the first item for its host is used.
+fn find(store: &PasswordStore, credential: &Credential) -> Result<Option<ItemName>, Error> {
+    if credential.username.is_some() {
+        return Ok(credential.item().filter(|item| store.exists(item)));
+    }
+
+    let prefix = match &credential.host {
+        Some(host) => format!("git/{}/", host),
+        None => return Ok(None),
+    };
+    Ok(store.list()?.into_iter().find(|item| {
+        item.as_str()
+            .strip_prefix(&prefix)
+            .is_some_and(|username| !username.contains('/'))
+    }))
+}
+
+fn get(store: &PasswordStore, config: &Config, credential: &Credential) -> Result<(), Error> {
+    // Only ask for the secret key before looking for the item if the store cannot be searched
+    // without it
+    let key = if store.needs_unlock() {
+        Some(super::open_with_key(store, config)?)
+    } else {
+        None
+    };
+    let item = match find(store, credential)? {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    let key = match key {
+        Some(key) => key,
+        None => super::secret_key(store, config, &crate::input::prompt)?,
+    };
+
+    let entry = store.get_entry(&item, &key)?;
+    super::check_rollback(store, config)?;
+
+    let username = item.as_str().rsplit('/').next().unwrap_or_default();
+    let response = Zeroizing::new(format!(
+        "username={}\npassword={}\n",
+        username, entry.password
+    ));
+    io::stdout().write_all(response.as_bytes())?;
+    Ok(())
+}
+
+fn store_credential(
+    store: &PasswordStore,
+    config: &Config,
+    credential: &Credential,
+) -> Result<(), Error> {
+    let (item, password) = match (credential.item(), &credential.password) {
+        (Some(item), Some(password)) => (item, password),
+        _ => return Ok(()),
+    };
+
+    if store.needs_unlock() || store.exists(&item) {
+        let key = super::open_with_key(store, config)?;
+        if store.exists(&item) {
+            let mut entry = store.get_entry(&item, &key)?;
+            if entry.password == password.as_str() {
+                return Ok(());
+            }
+            entry.password.clear();
+            entry.password.push_str(password);
+            store.update(&item, &Zeroizing::new(entry.to_string()))?;
+            super::git_commit(store, config, &format!("Update git credential {}.", item));
+            return Ok(());
+        }
+    }
+
+    let mut entry = Entry::new(password);
+    if let (Some(protocol), Some(host)) = (&credential.protocol, &credential.host) {
+        entry.url = Some(format!("{}://{}", protocol, host));
+    }
+    store.insert_entry(&item, &entry)?;
+    super::git_commit(store, config, &format!("Add git credential {}.", item));
+    Ok(())
+}
+
+fn erase(store: &PasswordStore, config: &Config, credential: &Credential) -> Result<(), Error> {
+    let key = super::open_with_key(store, config)?;
+    let item = match credential.item().filter(|item| store.exists(item)) {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+
+    // Only erase the password git was told about, not one stored since
+    if let Some(password) = &credential.password {
+        if store.get_entry(&item, &key)?.password != password.as_str() {
+            return Ok(());
+        }
+    }
+
+    store.delete(&item)?;
+    super::git_commit(store, config, &format!("Remove git credential {}.", item));
+    Ok(())
+}
+
+/// Act as a git credential helper: read a credential from standard input and get, store or erase
+/// it. Other operations are ignored, as git expects of helpers.
+pub fn git_credential(store: PasswordStore, config: &Config, operation: &str) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    let credential = Credential::read(io::stdin().lock())?;
+    match operation {
+        "get" => get(&store, config, &credential),
+        "store" => store_credential(&store, config, &credential),
+        "erase" => erase(&store, config, &credential),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_credentials() -> Result<(), Error> {
+        let input = "protocol=https\nhost=example.com:8443\nusername=alice\n\
+                     password=a=b\nwwwauth[]=Basic realm=x\n\nhost=ignored\n";
+        let credential = Credential::read(input.as_bytes())?;
+
+        assert_eq!(credential.protocol.as_deref(), Some("https"));
+        assert_eq!(
+            credential.password.as_deref().map(String::as_str),
+            Some("a=b")
+        );
+        assert_eq!(
+            credential.item().unwrap().as_str(),
+            "git/example.com:8443/alice"
+        );
+
+        let credential = Credential::read("host=example.com\n".as_bytes())?;
+        assert!(credential.item().is_none());
+
+        Ok(())
+    }
+}
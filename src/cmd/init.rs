@@ -1,31 +1,49 @@
-use age::x25519::{Identity, Recipient};
+use indicatif::{ProgressBar, ProgressStyle};
 use secrecy::ExposeSecret;
-use std::fs::{self, File};
-use std::io::prelude::*;
 
-use crate::error::Error;
-use crate::key;
-use crate::store::PasswordStore;
+use passage::{key, Error, Identity, ItemName, PasswordStore, Progress, Recipient};
+
+use crate::config::Config;
+use crate::input;
+
+/// Re-encrypt every item with a progress bar, then list the items that could not be re-encrypted.
+fn reencrypt(store: &PasswordStore, key: &Identity) -> Result<Vec<(ItemName, Error)>, Error> {
+    let bar = ProgressBar::new(0).with_style(
+        ProgressStyle::with_template("Re-encrypting {bar:40} {pos}/{len} {msg}").unwrap(),
+    );
+    let failures = store.reencrypt_with_progress(key, |progress: Progress| {
+        bar.set_length(progress.total as u64);
+        bar.set_position((progress.reencrypted + progress.failed) as u64);
+        if progress.failed > 0 {
+            bar.set_message(format!("({} failed)", progress.failed));
+        }
+    })?;
+    bar.finish_and_clear();
+
+    for (name, e) in &failures {
+        eprintln!("Could not re-encrypt {}: {}", name, e);
+    }
+
+    Ok(failures)
+}
 
 pub fn init(
     mut store: PasswordStore,
+    config: &Config,
     recipients: Option<Vec<Recipient>>,
     key_file: Option<String>,
+    hide_names: bool,
 ) -> Result<(), Error> {
-    if !store.dir.exists() {
-        fs::create_dir_all(&store.dir)?;
-    }
-
     let (existing_key, new_key) = match key_file {
         Some(key_file) => {
-            let new_key = key::read_secret_key(key_file)?;
-            match key::read_secret_key(crate::key::secret_key_path()) {
+            let new_key = key::read_secret_key(key_file, &input::prompt)?;
+            match super::secret_key(&store, config, &input::prompt) {
                 Ok(existing_key) => (Some(existing_key), Some(new_key)),
                 Err(Error::NoSecretKey) => (None, Some(new_key)),
                 Err(e) => return Err(e),
             }
         }
-        None => match key::read_secret_key(key::secret_key_path()) {
+        None => match super::secret_key(&store, config, &input::prompt) {
             Ok(existing_key) => (Some(existing_key), None),
             Err(Error::NoSecretKey) => (None, Some(Identity::generate())),
             Err(e) => return Err(e),
@@ -33,45 +51,53 @@ pub fn init(
     };
 
     if let Some(new_key) = &new_key {
-        store.recipients.push(new_key.to_public());
+        store.add_recipients(Some(new_key.to_public()));
     }
 
     // Add additional recipients
+    store.add_recipients(config.recipients.iter().cloned());
     if let Some(recipients) = recipients {
-        recipients
-            .into_iter()
-            .for_each(|r| store.recipients.push(r));
+        store.add_recipients(recipients);
     }
 
-    // Remove any duplicate recipients
-    store.recipients.sort_unstable_by_key(|r| r.to_string());
-    store.recipients.dedup_by_key(|r| r.to_string());
-
     if let (Some(existing_key), Some(new_key)) = (&existing_key, &new_key) {
         if existing_key.to_string().expose_secret() != new_key.to_string().expose_secret() {
             // Remove old public key from recipients
-            let pubkey = existing_key.to_public().to_string();
-            store.recipients.retain(|k| k.to_string() != pubkey);
+            store.remove_recipient(&existing_key.to_public());
         }
     };
 
+    let mut failures = vec![];
     if let Some(existing_key) = &existing_key {
-        store.reencrypt(&existing_key)?;
+        failures = reencrypt(&store, existing_key)?;
     }
 
     if let Some(new_key) = &new_key {
-        key::save_secret_key(new_key, key::secret_key_path(), true)?;
+        key::save_secret_key(new_key, config.identity()?, true)?;
     }
 
-    let mut file = File::create(store.dir.join(".public-keys"))?;
-    for recipient in &store.recipients {
-        writeln!(file, "{}", recipient)?;
+    store.save_recipients()?;
+
+    if let Some(key) = new_key.as_ref().or(existing_key.as_ref()) {
+        if hide_names {
+            store.hide_names(key)?;
+        }
+        store.sign_manifest(key)?;
+        super::trust_manifest(&store, config)?;
     }
+    super::git_commit(&store, config, "Initialize password store.");
 
     eprintln!("Initialized store with the following recipients:\n");
     for recipient in &store.recipients {
         eprintln!("    {}", recipient);
     }
 
+    if !failures.is_empty() {
+        return Err(Error::Other(format!(
+            "{} item(s) could not be re-encrypted and are still encrypted to the previous recipients.",
+            failures.len()
+        )));
+    }
+
     Ok(())
 }
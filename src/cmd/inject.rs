@@ -0,0 +1,172 @@
+use std::fs;
+use std::io::{self, prelude::*};
+use zeroize::Zeroizing;
+
+use passage::{atomic, Error, ItemName, PasswordStore};
+
+use crate::config::Config;
+
+/// A `{{ passage "ITEM" field="FIELD" }}` placeholder.
+#[derive(Debug, PartialEq)]
+struct Placeholder {
+    item: String,
+    field: Option<String>,
+}
+
+/// Parse a double-quoted string at the start of `s`, returning it and the rest of `s`.
+fn quoted(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    if !matches!(chars.next(), Some((_, '"'))) {
+        return Err(format!("expected a quoted string, not {:?}", s));
+    }
+
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &s[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+
+    Err(format!("unterminated string {}", s))
+}
+
+/// Parse the inside of a `{{ ... }}` placeholder, or return `None` if it is not one of ours.
+fn parse_placeholder(s: &str) -> Result<Option<Placeholder>, String> {
+    let mut rest = match s.trim().strip_prefix("passage") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+        _ => return Ok(None),
+    };
+
+    let (item, after) = quoted(rest)?;
+    rest = after.trim_start();
+
+    let mut field = None;
+    if let Some(after) = rest.strip_prefix("field=") {
+        let (value, after) = quoted(after)?;
+        field = Some(value);
+        rest = after.trim_start();
+    }
+
+    if !rest.is_empty() {
+        return Err(format!("unexpected {:?} in placeholder", rest));
+    }
+    Ok(Some(Placeholder { item, field }))
+}
+
+/// Replace every placeholder in `template` with the value returned by `lookup` for its item and
+/// field. Anything else between `{{` and `}}` is left as it is.
+fn render<F>(template: &str, mut lookup: F) -> Result<Zeroizing<String>, Error>
+where
+    F: FnMut(&str, Option<&str>) -> Result<Zeroizing<String>, Error>,
+{
+    // Every value is looked up first so that the output can be allocated at its final size, since
+    // growing it would leave copies of the values behind in freed memory
+    let mut parts: Vec<(&str, Option<Zeroizing<String>>)> = vec![];
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        let line = template[..template.len() - rest.len() + start]
+            .matches('\n')
+            .count()
+            + 1;
+
+        match parse_placeholder(&rest[start + 2..end]) {
+            Ok(Some(placeholder)) => {
+                let value = lookup(&placeholder.item, placeholder.field.as_deref())
+                    .map_err(|e| Error::Other(format!("line {}: {}", line, e)))?;
+                parts.push((&rest[..start], Some(value)));
+            }
+            Ok(None) => parts.push((&rest[..end + 2], None)),
+            Err(e) => return Err(Error::Other(format!("line {}: {}", line, e))),
+        }
+        rest = &rest[end + 2..];
+    }
+    parts.push((rest, None));
+
+    let len = parts
+        .iter()
+        .map(|(text, value)| text.len() + value.as_ref().map_or(0, |value| value.len()))
+        .sum();
+    let mut output = Zeroizing::new(String::with_capacity(len));
+    for (text, value) in &parts {
+        output.push_str(text);
+        if let Some(value) = value {
+            output.push_str(value);
+        }
+    }
+
+    Ok(output)
+}
+
+pub fn inject(
+    store: PasswordStore,
+    config: &Config,
+    input: Option<&str>,
+    output: Option<&str>,
+) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    let template = match input {
+        Some(path) if path != "-" => fs::read_to_string(path)?,
+        _ => {
+            let mut template = String::new();
+            io::stdin().read_to_string(&mut template)?;
+            template
+        }
+    };
+
+    let key = super::open_with_key(&store, config)?;
+    let rendered = render(&template, |item, field| {
+        let item: ItemName = item.parse()?;
+        super::lookup(&store, &key, &item, field)
+    })
+    .map_err(|e| match input {
+        Some(path) if path != "-" => Error::Other(format!("{}: {}", path, e)),
+        _ => e,
+    })?;
+
+    super::check_rollback(&store, config)?;
+
+    match output {
+        // A new file replacing `path`, readable only by the current user
+        Some(path) if path != "-" => Ok(atomic::write(path, rendered.as_bytes())?),
+        _ => Ok(io::stdout().write_all(rendered.as_bytes())?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering_templates() -> Result<(), Error> {
+        let template = "user={{ passage \"prod/db\" field=\"username\" }}\n\
+                        password={{passage \"prod/db\"}}\n\
+                        other={{ .Values.x }} {{ passage \"a \\\"quoted\\\" name\" }}\n";
+        let rendered = render(template, |item, field| {
+            Ok(Zeroizing::new(format!("{}#{}", item, field.unwrap_or("-"))))
+        })?;
+        assert_eq!(
+            *rendered,
+            "user=prod/db#username\npassword=prod/db#-\nother={{ .Values.x }} a \"quoted\" name#-\n"
+        );
+        assert_eq!(rendered.capacity(), rendered.len());
+
+        let error = render("\n{{ passage prod/db }}", |_, _| unreachable!()).unwrap_err();
+        assert!(error.to_string().starts_with("line 2: "));
+        assert!(render("{{ passage \"x\" mode=1 }}", |_, _| unreachable!()).is_err());
+
+        Ok(())
+    }
+}
@@ -1,14 +1,37 @@
-use crate::error::Error;
+use std::fs::File;
+use zeroize::Zeroizing;
+
+use passage::{Entry, Error, ExposeSecret, ItemName, PasswordStore};
+
+use crate::config::Config;
 use crate::input;
-use crate::store::PasswordStore;
 
-pub fn insert(store: PasswordStore, item: Option<&str>, force: bool) -> Result<(), Error> {
+pub fn insert(
+    store: PasswordStore,
+    config: &Config,
+    item: Option<&str>,
+    force: bool,
+    fields: &[(&str, &str)],
+    file: Option<&str>,
+) -> Result<(), Error> {
     let item = match item {
         Some(s) => s.to_string(),
-        None => input::read_input("Enter the name of the item you wish to add to your store.\n>")?,
+        None => match super::choose(
+            &store,
+            config,
+            "Enter the name of the item you wish to add to your store.\n>",
+            true,
+        )? {
+            Some(item) => item,
+            None => return Ok(()),
+        },
     };
+    let item: ItemName = item.parse()?;
 
-    if store.exists(&item) && !force {
+    super::unlock_store(&store, config)?;
+
+    let exists = store.exists(&item);
+    if exists && !force {
         let ans = input::read_input(&format!(
             "{} already exists in the password store. Overwrite? [y/N]",
             item
@@ -19,12 +42,35 @@ pub fn insert(store: PasswordStore, item: Option<&str>, force: bool) -> Result<(
         }
     }
 
-    let password = input::read_secret(
-        &format!("Enter password for {}", item),
-        Some(&format!("Retype password for {}", item)),
-    )?;
+    if let Some(path) = file {
+        let file = File::open(path)?;
+        if exists {
+            store.update_from(&item, file)?;
+        } else {
+            store.insert_from(&item, file)?;
+        }
+    } else {
+        let password = input::read_secret(
+            &format!("Enter password for {}", item),
+            Some(&format!("Retype password for {}", item)),
+        )?;
+
+        let mut entry = Entry::new(password.expose_secret());
+        for (key, value) in fields {
+            entry.set(key, value);
+        }
 
-    store.insert(&item, &password)?;
+        if exists {
+            store.update(&item, &Zeroizing::new(entry.to_string()))?;
+        } else {
+            store.insert_entry(&item, &entry)?;
+        }
+    }
+    super::git_commit(
+        &store,
+        config,
+        &format!("Add given password for {} to store.", item),
+    );
     eprintln!("Created new entry in the password store for {}.", item);
     Ok(())
 }
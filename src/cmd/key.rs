@@ -1,10 +1,19 @@
-use crate::error::Error;
-use crate::key;
 use secrecy::ExposeSecret;
 
-pub fn key(secret: bool) -> Result<(), Error> {
-    let key = key::read_secret_key(key::secret_key_path())?;
-    if secret {
+use passage::{key, Error};
+
+use crate::config::Config;
+use crate::input;
+
+pub fn key(config: &Config, secret: bool, json: bool) -> Result<(), Error> {
+    let key = key::read_secret_key(config.identity()?, &input::prompt)?;
+    if json {
+        let mut output = serde_json::json!({ "public_key": key.to_public().to_string() });
+        if secret {
+            output["secret_key"] = key.to_string().expose_secret().into();
+        }
+        println!("{}", output);
+    } else if secret {
         println!("{}", key.to_string().expose_secret());
     } else {
         println!("{}", key.to_public());
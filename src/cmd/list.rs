@@ -1,61 +1,70 @@
 use colored::*;
-use std::fs::{self, DirEntry};
-use std::io;
-use std::path::Path;
-
-use crate::error::Error;
-use crate::store::PasswordStore;
-
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map_or(false, |s| s.starts_with('.'))
-}
+use std::collections::BTreeMap;
 
-fn tree(root: &Path, depth: usize, prefix: &str) -> io::Result<()> {
-    let mut entries = fs::read_dir(root)?
-        .filter_map(|e| e.ok())
-        .filter(|e| !is_hidden(e))
-        .collect::<Vec<DirEntry>>();
+use passage::{Error, ItemName, PasswordStore};
 
-    entries.sort_by_key(|k| k.file_name());
+use crate::config::Config;
 
-    for (i, entry) in entries.iter().enumerate() {
-        let is_last = i == entries.len() - 1;
-        if is_last {
-            print!("{}└── ", prefix);
-        } else {
-            print!("{}├── ", prefix);
-        }
+/// Items and directories of the store, keyed by name and whether the entry is a directory.
+#[derive(Default)]
+struct Tree(BTreeMap<(String, bool), Tree>);
 
-        let path = entry.path();
-        let file_name = entry.file_name().into_string().unwrap();
+impl Tree {
+    fn new(items: &[ItemName]) -> Tree {
+        let mut root = Tree::default();
+        for item in items {
+            let mut components = item
+                .as_str()
+                .split('/')
+                .filter(|c| !c.is_empty())
+                .peekable();
+            let mut node = &mut root;
+            while let Some(component) = components.next() {
+                let is_dir = components.peek().is_some();
+                node = node.0.entry((component.to_string(), is_dir)).or_default();
+            }
+        }
+        root
+    }
 
-        if path.is_dir() {
-            println!("{}", file_name.blue().bold());
-            let next_prefix = prefix.to_string() + if is_last { "    " } else { "│   " };
-            tree(&path, depth + 1, &next_prefix)?;
-        } else {
-            let file_name = match file_name.strip_suffix(".age") {
-                Some(e) => e,
-                _ => &file_name,
-            };
+    fn print(&self, prefix: &str) {
+        for (i, ((name, is_dir), children)) in self.0.iter().enumerate() {
+            let is_last = i == self.0.len() - 1;
+            if is_last {
+                print!("{}└── ", prefix);
+            } else {
+                print!("{}├── ", prefix);
+            }
 
-            println!("{}", file_name);
+            if *is_dir {
+                println!("{}", name.blue().bold());
+                let next_prefix = prefix.to_string() + if is_last { "    " } else { "│   " };
+                children.print(&next_prefix);
+            } else {
+                println!("{}", name);
+            }
         }
     }
-
-    Ok(())
 }
 
-pub fn list(store: PasswordStore) -> Result<(), Error> {
-    if !store.dir.exists() {
+pub fn list(store: PasswordStore, config: &Config, json: bool) -> Result<(), Error> {
+    if !store.is_initialized()? {
         return Err(Error::StoreNotInitialized);
     }
 
+    super::unlock_store(&store, config)?;
+    for problem in super::check_manifest(&store, config)? {
+        eprintln!("Warning: {}", problem);
+    }
+
+    let items = store.list()?;
+    if json {
+        println!("{}", serde_json::to_string(&items)?);
+        return Ok(());
+    }
+
     println!("Password Store");
-    tree(&store.dir, 1, "")?;
+    Tree::new(&items).print("");
 
     Ok(())
 }
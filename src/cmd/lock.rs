@@ -1,17 +1,33 @@
-use crate::error::Error;
+use passage::{key, Error};
+
+use crate::config::Config;
 use crate::input;
-use crate::key;
 
-pub fn lock() -> Result<(), Error> {
+pub fn lock(config: &Config, work_factor: Option<u8>) -> Result<(), Error> {
     let passphrase = input::read_secret("Enter passphrase", Some("Confirm passphrase"))?;
-    key::encrypt_secret_key(key::secret_key_path(), &passphrase)?;
+    key::encrypt_secret_key(config.identity()?, &passphrase, work_factor)?;
 
     eprintln!("Password store locked.");
     Ok(())
 }
 
-pub fn unlock() -> Result<(), Error> {
-    key::decrypt_secret_key(key::secret_key_path(), None)?;
+pub fn unlock(config: &Config) -> Result<(), Error> {
+    let passphrase = input::read_secret("Passphrase", None)?;
+    key::decrypt_secret_key(config.identity()?, &passphrase)?;
     eprintln!("Password store unlocked.");
     Ok(())
 }
+
+pub fn passwd(config: &Config, work_factor: Option<u8>) -> Result<(), Error> {
+    let path = config.identity()?;
+    if !key::is_locked(path)? {
+        return Err(Error::KeyNotEncrypted);
+    }
+
+    let old = input::read_secret("Enter current passphrase", None)?;
+    let new = input::read_secret("Enter new passphrase", Some("Confirm new passphrase"))?;
+    key::change_passphrase(path, &old, &new, work_factor)?;
+
+    eprintln!("Passphrase changed.");
+    Ok(())
+}
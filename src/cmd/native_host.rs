@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, prelude::*};
+use zeroize::Zeroizing;
+
+use passage::{Entry, Error, Identity, ItemName, PasswordStore};
+
+use crate::config::Config;
+
+/// The largest message accepted from the browser, far more than any request needs.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+/// A request from the browser extension.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Request {
+    /// List every item.
+    List,
+    /// List the items for the site at `url`, best matches first.
+    Search { url: String },
+    /// Decrypt an item.
+    Fetch { item: String },
+    /// Decrypt the login and password of `item` or, if not given, of the best match for `url`.
+    Fill { url: String, item: Option<String> },
+}
+
+#[derive(Serialize)]
+struct Response<T> {
+    status: &'static str,
+    data: T,
+}
+
+#[derive(Serialize)]
+struct Login<'a> {
+    item: &'a str,
+    username: Option<&'a str>,
+    password: &'a str,
+}
+
+/// Read a message: its length as a 32-bit integer in native byte order, then that many bytes of
+/// JSON. Returns `None` once the browser closes the connection.
+fn read_message<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_ne_bytes(len) as usize;
+    if len > MAX_MESSAGE {
+        return Err(Error::Other(format!(
+            "Message of {} bytes is too long.",
+            len
+        )));
+    }
+
+    let mut message = vec![0; len];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &[u8]) -> Result<(), Error> {
+    output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    output.write_all(message)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// The host of `url`, without its port, in lower case.
+fn host(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+    host.to_lowercase()
+}
+
+/// The items for the site at `url`: those with a path component equal to its host or to one of
+/// its parent domains, such as `github.com` for `https://gist.github.com/`. Items for more
+/// specific domains come first.
+fn search(url: &str, items: &[ItemName]) -> Vec<ItemName> {
+    let host = host(url);
+    let mut domains = vec![host.as_str()];
+    let mut rest = host.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        if !parent.contains('.') {
+            break;
+        }
+        domains.push(parent);
+        rest = parent;
+    }
+
+    let mut found: Vec<(usize, &ItemName)> = items
+        .iter()
+        .filter_map(|item| {
+            let rank = item.as_str().split('/').filter_map(|component| {
+                let component = component.to_lowercase();
+                domains.iter().position(|domain| *domain == component)
+            });
+            rank.min().map(|rank| (rank, item))
+        })
+        .collect();
+    found.sort_by_key(|(rank, item)| (*rank, item.as_str()));
+    found.into_iter().map(|(_, item)| item.clone()).collect()
+}
+
+/// A connection with the browser extension, reading the secret key when it is first needed.
+struct Session<'a> {
+    store: PasswordStore,
+    config: &'a Config,
+    key: Option<Identity>,
+}
+
+impl Session<'_> {
+    fn key(&mut self) -> Result<&Identity, Error> {
+        if self.key.is_none() {
+            self.key = Some(super::open_with_key(&self.store, self.config)?);
+        }
+        Ok(self.key.as_ref().unwrap())
+    }
+
+    fn list(&mut self) -> Result<Vec<ItemName>, Error> {
+        if self.store.needs_unlock() {
+            self.key()?;
+        }
+        self.store.list()
+    }
+
+    fn entry(&mut self, item: &ItemName) -> Result<Entry, Error> {
+        self.key()?;
+        let entry = self.store.get_entry(item, self.key.as_ref().unwrap())?;
+        super::check_rollback(&self.store, self.config)?;
+        Ok(entry)
+    }
+
+    fn handle(&mut self, request: Request) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let ok = |data| -> Result<Zeroizing<Vec<u8>>, Error> {
+            Ok(Zeroizing::new(serde_json::to_vec(&Response {
+                status: "ok",
+                data,
+            })?))
+        };
+
+        match request {
+            Request::List => ok(serde_json::to_value(self.list()?)?),
+            Request::Search { url } => ok(serde_json::to_value(search(&url, &self.list()?))?),
+            Request::Fetch { item } => {
+                let entry = self.entry(&item.parse()?)?;
+                Ok(Zeroizing::new(serde_json::to_vec(&Response {
+                    status: "ok",
+                    data: &entry,
+                })?))
+            }
+            Request::Fill { url, item } => {
+                let item: ItemName = match item {
+                    Some(item) => item.parse()?,
+                    None => match search(&url, &self.list()?).into_iter().next() {
+                        Some(item) => item,
+                        None => return Err(Error::ItemNotFound(host(&url))),
+                    },
+                };
+                let entry = self.entry(&item)?;
+                Ok(Zeroizing::new(serde_json::to_vec(&Response {
+                    status: "ok",
+                    data: Login {
+                        item: item.as_str(),
+                        username: entry.username.as_deref(),
+                        password: &entry.password,
+                    },
+                })?))
+            }
+        }
+    }
+}
+
+/// Act as the native messaging host of a browser extension, answering requests until the browser
+/// closes standard input.
+pub fn native_host(store: PasswordStore, config: &Config) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    let mut session = Session {
+        store,
+        config,
+        key: None,
+    };
+    let (mut input, mut output) = (io::stdin().lock(), io::stdout().lock());
+
+    while let Some(message) = read_message(&mut input)? {
+        let message = Zeroizing::new(message);
+        let response = serde_json::from_slice(&message)
+            .map_err(Error::from)
+            .and_then(|request| session.handle(request))
+            .unwrap_or_else(|e| {
+                let error = serde_json::json!({ "status": "error", "message": e.to_string() });
+                Zeroizing::new(error.to_string().into_bytes())
+            });
+        write_message(&mut output, &response)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_messages() -> Result<(), Error> {
+        let mut buffer = vec![];
+        write_message(&mut buffer, br#"{"action":"list"}"#)?;
+        write_message(&mut buffer, br#"{"action":"fill","url":"https://x"}"#)?;
+
+        let mut input = buffer.as_slice();
+        let request: Request = serde_json::from_slice(&read_message(&mut input)?.unwrap())?;
+        assert_eq!(request, Request::List);
+        let request: Request = serde_json::from_slice(&read_message(&mut input)?.unwrap())?;
+        assert_eq!(
+            request,
+            Request::Fill {
+                url: "https://x".into(),
+                item: None
+            }
+        );
+        assert!(read_message(&mut input)?.is_none());
+
+        let mut input: &[u8] = &u32::MAX.to_ne_bytes();
+        assert!(read_message(&mut input).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn searching_items_for_a_site() -> Result<(), Error> {
+        let items: Vec<ItemName> = [
+            "email/example.com",
+            "github.com",
+            "sites/gist.github.com/alice",
+            "work/GitHub.com/bob",
+            "github.community",
+        ]
+        .iter()
+        .map(|name| name.parse())
+        .collect::<Result<_, _>>()?;
+
+        let names = |url| -> Vec<String> {
+            search(url, &items)
+                .iter()
+                .map(|item| item.to_string())
+                .collect()
+        };
+        assert_eq!(
+            names("https://user@gist.github.com:443/x?y#z"),
+            [
+                "sites/gist.github.com/alice",
+                "github.com",
+                "work/GitHub.com/bob"
+            ]
+        );
+        assert_eq!(names("example.com"), ["email/example.com"]);
+        assert!(names("https://com/").is_empty());
+
+        Ok(())
+    }
+}
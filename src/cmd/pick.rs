@@ -0,0 +1,20 @@
+use passage::{Error, PasswordStore};
+
+use crate::config::Config;
+
+pub fn pick(store: PasswordStore, config: &Config) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+    if !crate::pick::available() {
+        return Err(Error::Other("passage pick needs a terminal.".into()));
+    }
+
+    match super::choose(&store, config, "", false)? {
+        Some(item) => {
+            println!("{}", item);
+            Ok(())
+        }
+        None => Err(Error::Other("No item selected.".into())),
+    }
+}
@@ -1,17 +1,32 @@
-use crate::error::Error;
+use passage::{Error, ItemName, PasswordStore};
+
+use crate::config::Config;
 use crate::input;
-use crate::store::PasswordStore;
 
-pub fn remove(store: PasswordStore, item: Option<&str>, force: bool) -> Result<(), Error> {
+pub fn remove(
+    store: PasswordStore,
+    config: &Config,
+    item: Option<&str>,
+    force: bool,
+) -> Result<(), Error> {
     let item = match item {
         Some(s) => s.to_string(),
-        None => {
-            input::read_input("Enter the name of the item you wish to remove from your store.\n>")?
-        }
+        None => match super::choose(
+            &store,
+            config,
+            "Enter the name of the item you wish to remove from your store.\n>",
+            false,
+        )? {
+            Some(item) => item,
+            None => return Ok(()),
+        },
     };
+    let item: ItemName = item.parse()?;
+
+    super::unlock_store(&store, config)?;
 
     if !store.exists(&item) {
-        return Err(Error::ItemNotFound(item));
+        return Err(Error::ItemNotFound(item.to_string()));
     }
 
     if !force {
@@ -22,6 +37,7 @@ pub fn remove(store: PasswordStore, item: Option<&str>, force: bool) -> Result<(
     }
 
     store.delete(&item)?;
+    super::git_commit(&store, config, &format!("Remove {} from store.", item));
     eprintln!("Removed {} from the password store.", item);
     Ok(())
 }
@@ -1,16 +1,73 @@
-use clipboard::{ClipboardContext, ClipboardProvider};
+use std::io::{prelude::*, BufWriter};
+use zeroize::Zeroizing;
 
-use crate::error::Error;
-use crate::store::PasswordStore;
+use passage::{atomic, Entry, Error, ExposeSecret, Identity, ItemName, PasswordStore};
 
-pub fn show(store: PasswordStore, item: &str, copy_to_clipboard: bool) -> Result<(), Error> {
-    let secret = store.get(item)?;
+use crate::config::Config;
+use crate::{clip, input};
 
+/// Decrypt `item` into a new file replacing `path`, readable only by the current user. Nothing is
+/// written to `path` if anything goes wrong.
+fn save(
+    store: &PasswordStore,
+    config: &Config,
+    item: &ItemName,
+    key: &Identity,
+    path: &str,
+) -> Result<(), Error> {
+    let mut file = atomic::Writer::new(path)?;
+    let mut output = BufWriter::new(&mut file);
+    store.get_to(item, key, &mut output)?;
+    output.flush()?;
+    drop(output);
+
+    super::check_rollback(store, config)?;
+    file.commit(true)?;
+
+    Ok(())
+}
+
+pub fn show(
+    store: PasswordStore,
+    config: &Config,
+    item: Option<&str>,
+    copy_to_clipboard: bool,
+    json: bool,
+    output: Option<&str>,
+) -> Result<(), Error> {
+    let item = match item {
+        Some(item) => item.to_string(),
+        None => match super::choose(
+            &store,
+            config,
+            "Enter the name of the item you wish to show.\n>",
+            false,
+        )? {
+            Some(item) => item,
+            None => return Ok(()),
+        },
+    };
+    let item: ItemName = item.parse()?;
+    let prompt = |_: &str| input::read_secret(&format!("Passphrase to show {}", item), None);
+    let key = super::secret_key(&store, config, &prompt)?;
+
+    if let Some(path) = output {
+        save(&store, config, &item, &key, path)?;
+        eprintln!("Saved {} to {}.", item, path);
+        return Ok(());
+    }
+
+    let secret = store.get(&item, &key)?;
+    super::check_rollback(&store, config)?;
+
+    let secret = secret.expose_secret();
     if copy_to_clipboard {
         let first_line = secret.split('\n').next().unwrap_or("");
-        let mut ctx: ClipboardContext = ClipboardProvider::new()?;
-        ctx.set_contents(first_line.to_string())?;
-        eprintln!("Copied password for {} to clipboard.", item);
+        clip::copy(first_line, config.clip_timeout)?;
+        super::copied(&item, config);
+    } else if json {
+        let json = Zeroizing::new(serde_json::to_string(&Entry::parse(secret))?);
+        println!("{}", *json);
     } else {
         println!("{}", secret);
     }
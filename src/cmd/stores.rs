@@ -0,0 +1,80 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use passage::{Error, PasswordStore};
+
+use crate::config::{Config, Stores};
+
+fn read_stores(config: &Config) -> Result<Stores, Error> {
+    match &config.stores_file {
+        Some(path) => Stores::read(path),
+        None => Err(Error::Other("No configuration directory found.".into())),
+    }
+}
+
+fn absolute(path: &Path) -> Result<PathBuf, Error> {
+    Ok(env::current_dir()?.join(path))
+}
+
+pub fn list_stores(config: &Config, json: bool) -> Result<(), Error> {
+    let stores = read_stores(config)?;
+
+    if json {
+        let stores: Vec<_> = stores
+            .list()
+            .map(|(name, dir)| serde_json::json!({ "name": name, "store": dir }))
+            .collect();
+        println!("{}", serde_json::to_string(&stores)?);
+        return Ok(());
+    }
+
+    for (name, dir) in stores.list() {
+        let current = if config.profile.as_deref() == Some(name) {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {}\t{}", current, name, dir.display());
+    }
+
+    Ok(())
+}
+
+pub fn add_store(
+    config: &Config,
+    name: &str,
+    dir: &str,
+    identities: &[PathBuf],
+) -> Result<(), Error> {
+    let dir = absolute(Path::new(dir))?;
+    let identities = identities
+        .iter()
+        .map(|path| absolute(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stores = read_stores(config)?;
+    stores.add(name, &dir, &identities)?;
+    stores.save()?;
+
+    eprintln!("Added store {} in {}.", name, dir.display());
+    if !PasswordStore::new(dir).is_initialized()? {
+        eprintln!(
+            "Run \"passage --store {} init\" to create the password store.",
+            name
+        );
+    }
+    Ok(())
+}
+
+pub fn remove_store(config: &Config, name: &str) -> Result<(), Error> {
+    let mut stores = read_stores(config)?;
+    let dir = stores.remove(name)?;
+    stores.save()?;
+
+    eprintln!(
+        "Removed store {}. The password store in {} was left as it is.",
+        name,
+        dir.display()
+    );
+    Ok(())
+}
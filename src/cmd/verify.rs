@@ -0,0 +1,40 @@
+use passage::{Error, PasswordStore};
+
+use crate::config::Config;
+
+pub fn verify(store: PasswordStore, config: &Config, trust: bool) -> Result<(), Error> {
+    if !store.is_initialized()? {
+        return Err(Error::StoreNotInitialized);
+    }
+
+    super::unlock_store(&store, config)?;
+    let manifest = match store.manifest()? {
+        Some(manifest) => manifest,
+        None => return Err(Error::Other(
+            "Password store does not keep a manifest. Run \"passage init\" to start keeping one."
+                .into(),
+        )),
+    };
+
+    if trust {
+        super::trust_manifest(&store, config)?;
+    }
+
+    let problems = super::check_manifest(&store, config)?;
+    if problems.is_empty() {
+        println!(
+            "Password store matches its manifest (version {}).",
+            manifest.version
+        );
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    Err(Error::Other(format!(
+        "Found {} problem(s) with the password store.",
+        problems.len()
+    )))
+}
@@ -0,0 +1,632 @@
+//! Settings read from configuration files and the environment.
+//!
+//! Settings are read from `$XDG_CONFIG_HOME/passage/config.toml` (or the file named by
+//! `PASSAGE_CONFIG`) and then from `.config.toml` in the password store, whose settings take
+//! precedence. A store can only set a few harmless settings (see [`StoreSettings`]). Environment
+//! variables override both.
+//!
+//! Several stores can be registered by name in `stores.toml`, next to the configuration file, each
+//! with its own settings. Those of the store selected with `--store` or `PASSAGE_PROFILE` take
+//! precedence over the configuration file.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use passage::{atomic, key, perms, Error, ItemName, Recipient};
+
+/// The file in the password store holding its settings.
+pub const STORE_CONFIG: &str = ".config.toml";
+
+/// The file, next to the configuration file, listing the stores registered by name.
+pub const STORES_FILE: &str = "stores.toml";
+
+/// Whether to color output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// Only when writing to a terminal.
+    Auto,
+    /// Always.
+    Always,
+    /// Never.
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!("expected auto, always or never, not {:?}", s)),
+        }
+    }
+}
+
+/// One or several paths.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Paths {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Paths {
+    fn expand(&self) -> Vec<PathBuf> {
+        match self {
+            Paths::One(path) => vec![expand(path)],
+            Paths::Many(paths) => paths.iter().map(|path| expand(path)).collect(),
+        }
+    }
+}
+
+/// The `[generator]` table.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct GeneratorSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    charset: Option<String>,
+}
+
+impl GeneratorSettings {
+    fn is_empty(&self) -> bool {
+        self.length.is_none() && self.charset.is_none()
+    }
+}
+
+/// The contents of a configuration file, where every setting is optional.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<Paths>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clip_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    editor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_auto_commit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipients: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_memory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    docker_prefix: Option<String>,
+    // Tables come last in TOML
+    #[serde(skip_serializing_if = "GeneratorSettings::is_empty")]
+    generator: GeneratorSettings,
+}
+
+/// The settings a store can set in its own [`STORE_CONFIG`]. A store may be shared with, or
+/// writable by, others, so it can only set what cannot make us run programs, read another secret
+/// key, encrypt to someone else or otherwise weaken our protections.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct StoreSettings {
+    clip_timeout: Option<u64>,
+    color: Option<Color>,
+    generator: GeneratorSettings,
+}
+
+impl From<StoreSettings> for Settings {
+    fn from(settings: StoreSettings) -> Settings {
+        Settings {
+            clip_timeout: settings.clip_timeout,
+            color: settings.color,
+            generator: settings.generator,
+            ..Settings::default()
+        }
+    }
+}
+
+/// Read the settings in the file at `path`, which need not exist.
+fn read_settings<T: DeserializeOwned + Default>(path: &Path) -> Result<T, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    toml::from_str(&contents).map_err(|e| {
+        Error::Other(format!(
+            "Invalid configuration in {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+impl Settings {
+    /// Combine two sets of settings, preferring those in `other`.
+    fn merge(self, other: Settings) -> Settings {
+        Settings {
+            store: other.store.or(self.store),
+            identity: other.identity.or(self.identity),
+            clip_timeout: other.clip_timeout.or(self.clip_timeout),
+            generator: GeneratorSettings {
+                length: other.generator.length.or(self.generator.length),
+                charset: other.generator.charset.or(self.generator.charset),
+            },
+            editor: other.editor.or(self.editor),
+            color: other.color.or(self.color),
+            git_auto_commit: other.git_auto_commit.or(self.git_auto_commit),
+            recipients: other.recipients.or(self.recipients),
+            lock_memory: other.lock_memory.or(self.lock_memory),
+            docker_prefix: other.docker_prefix.or(self.docker_prefix),
+        }
+    }
+
+    /// Override settings with the environment variables returned by `var`.
+    fn from_env(var: &dyn Fn(&str) -> Option<String>) -> Result<Settings, Error> {
+        fn parse<T: FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, Error>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|e| Error::Other(format!("Invalid value for {}: {}", name, e)))
+                })
+                .transpose()
+        }
+
+        let flag = |name: &str| -> Result<Option<bool>, Error> {
+            match var(name).as_deref() {
+                None => Ok(None),
+                Some("1") | Some("true") | Some("yes") => Ok(Some(true)),
+                Some("0") | Some("false") | Some("no") | Some("") => Ok(Some(false)),
+                Some(value) => Err(Error::Other(format!(
+                    "Invalid value for {}: expected 1 or 0, not {:?}",
+                    name, value
+                ))),
+            }
+        };
+
+        Ok(Settings {
+            store: var("PASSAGE_STORE_DIR"),
+            identity: var("PASSAGE_IDENTITIES_FILE").map(|value| {
+                Paths::Many(
+                    env::split_paths(&value)
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect(),
+                )
+            }),
+            clip_timeout: parse("PASSAGE_CLIP_TIME", var("PASSAGE_CLIP_TIME"))?,
+            generator: GeneratorSettings {
+                length: parse("PASSAGE_GENERATED_LENGTH", var("PASSAGE_GENERATED_LENGTH"))?,
+                charset: var("PASSAGE_CHARACTER_SET"),
+            },
+            editor: var("PASSAGE_EDITOR"),
+            color: parse("PASSAGE_COLOR", var("PASSAGE_COLOR"))?,
+            git_auto_commit: flag("PASSAGE_GIT_AUTO_COMMIT")?,
+            recipients: var("PASSAGE_RECIPIENTS")
+                .map(|value| value.split_whitespace().map(str::to_string).collect()),
+            lock_memory: flag("PASSAGE_LOCK_MEMORY")?,
+            docker_prefix: var("PASSAGE_DOCKER_PREFIX"),
+        })
+    }
+}
+
+/// The stores registered by name, as kept in [`STORES_FILE`].
+pub struct Stores {
+    path: PathBuf,
+    stores: BTreeMap<String, Settings>,
+}
+
+impl Stores {
+    /// Read the registered stores from the file at `path`, which need not exist.
+    pub fn read(path: &Path) -> Result<Stores, Error> {
+        let stores = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                Error::Other(format!("Invalid stores in {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Stores {
+            path: path.to_path_buf(),
+            stores,
+        })
+    }
+
+    /// The name and directory of every registered store, sorted by name.
+    pub fn list(&self) -> impl Iterator<Item = (&str, PathBuf)> {
+        self.stores.iter().map(|(name, settings)| {
+            let dir = settings.store.as_deref().map(expand).unwrap_or_default();
+            (name.as_str(), dir)
+        })
+    }
+
+    /// Register the store in `dir` as `name`, reading its secret key from `identities` if any
+    /// are given.
+    pub fn add(&mut self, name: &str, dir: &Path, identities: &[PathBuf]) -> Result<(), Error> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::Other(format!(
+                "{:?} is not a valid store name: use letters, digits, '-' and '_'.",
+                name
+            )));
+        }
+        if self.stores.contains_key(name) {
+            return Err(Error::Other(format!(
+                "A store named {} already exists.",
+                name
+            )));
+        }
+
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let settings = Settings {
+            store: Some(path(dir)),
+            identity: match identities {
+                [] => None,
+                [identity] => Some(Paths::One(path(identity))),
+                _ => Some(Paths::Many(identities.iter().map(|p| path(p)).collect())),
+            },
+            ..Settings::default()
+        };
+        self.stores.insert(name.to_string(), settings);
+        Ok(())
+    }
+
+    /// Forget the store registered as `name`, returning its directory.
+    pub fn remove(&mut self, name: &str) -> Result<PathBuf, Error> {
+        match self.stores.remove(name) {
+            Some(settings) => Ok(settings.store.as_deref().map(expand).unwrap_or_default()),
+            None => Err(Stores::not_found(name)),
+        }
+    }
+
+    /// Write the registered stores back to their file, replacing it atomically so that it is never
+    /// left half written.
+    pub fn save(&self) -> Result<(), Error> {
+        let contents = toml::to_string(&self.stores)
+            .map_err(|e| Error::Other(format!("Could not save stores: {}", e)))?;
+        if let Some(parent) = self.path.parent() {
+            perms::create_dir_all(parent)?;
+        }
+        atomic::write(&self.path, contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn not_found(name: &str) -> Error {
+        Error::Other(format!(
+            "No store named {}. See \"passage stores list\".",
+            name
+        ))
+    }
+
+    /// Take the settings of the store registered as `name`.
+    fn take(&mut self, name: &str) -> Result<Settings, Error> {
+        let settings = self
+            .stores
+            .remove(name)
+            .ok_or_else(|| Stores::not_found(name))?;
+        if settings.store.is_none() {
+            return Err(Error::Other(format!(
+                "Store {} in {} has no \"store\" directory.",
+                name,
+                self.path.display()
+            )));
+        }
+        Ok(settings)
+    }
+}
+
+/// Expand a leading `~` in a path to the home directory.
+fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The settings in effect.
+#[derive(Debug)]
+pub struct Config {
+    /// The directory of the password store.
+    pub store_dir: PathBuf,
+    /// The files holding the secret key, of which the first is the one created by `passage init`.
+    /// Empty if there is no default location for it.
+    pub identities: Vec<PathBuf>,
+    /// Seconds after which a password copied to the clipboard is cleared, or 0 to never clear it.
+    pub clip_timeout: u64,
+    /// The length of generated passwords.
+    pub length: usize,
+    /// The characters generated passwords are made of.
+    pub charset: Vec<char>,
+    /// The command used by `passage edit` to edit items, if any.
+    pub editor: Option<String>,
+    /// Whether to color output.
+    pub color: Color,
+    /// Whether to commit every change to the store if it is a git repository.
+    pub git_auto_commit: bool,
+    /// Recipients added to every store by `passage init`.
+    pub recipients: Vec<Recipient>,
+    /// Whether to lock all memory into RAM, see [`passage::protect::lock_memory`].
+    pub lock_memory: bool,
+    /// The directory of the store that `passage docker-credential` keeps registry credentials in.
+    pub docker_prefix: String,
+    /// The name of the registered store in use, if any.
+    pub profile: Option<String>,
+    /// The file listing the registered stores, if there is a configuration directory.
+    pub stores_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// The file holding the secret key, for commands that create, lock or unlock it.
+    pub fn identity(&self) -> Result<&Path, Error> {
+        self.identities
+            .first()
+            .map(PathBuf::as_path)
+            .ok_or(Error::NoDataDir)
+    }
+
+    /// Read the settings from the configuration files and the environment, using the store
+    /// registered as `profile` or, if not given, as `PASSAGE_PROFILE`.
+    pub fn load(profile: Option<&str>) -> Result<Config, Error> {
+        let path = match env::var_os("PASSAGE_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("passage").join("config.toml")),
+        };
+        let user = match &path {
+            Some(path) => read_settings(path)?,
+            None => Settings::default(),
+        };
+        let env = Settings::from_env(&|name| env::var(name).ok())?;
+
+        let stores_file = path.map(|path| path.with_file_name(STORES_FILE));
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| env::var("PASSAGE_PROFILE").ok())
+            .filter(|profile| !profile.is_empty());
+        let profile_settings =
+            match (&profile, &stores_file) {
+                (Some(_), _) if env.store.is_some() => return Err(Error::Other(
+                    "PASSAGE_STORE_DIR cannot be used together with --store or PASSAGE_PROFILE."
+                        .into(),
+                )),
+                (Some(profile), Some(file)) => Some(Stores::read(file)?.take(profile)?),
+                (Some(profile), None) => return Err(Stores::not_found(profile)),
+                (None, _) => None,
+            };
+
+        let mut config = Config::resolve(user, profile_settings, env, |dir| {
+            read_settings(&dir.join(STORE_CONFIG))
+        })?;
+        config.profile = profile;
+        config.stores_file = stores_file;
+        Ok(config)
+    }
+
+    /// Combine the user's settings with those of the registered store in use, if any, those of the
+    /// store they point to, read by `store`, and those from the environment.
+    fn resolve(
+        user: Settings,
+        profile: Option<Settings>,
+        env: Settings,
+        store: impl FnOnce(&Path) -> Result<StoreSettings, Error>,
+    ) -> Result<Config, Error> {
+        let user = match profile {
+            Some(profile) => user.merge(profile),
+            None => user,
+        };
+        let store_dir = match env.store.as_deref().or(user.store.as_deref()) {
+            Some(dir) => expand(dir),
+            None => dirs::home_dir()
+                .ok_or_else(|| Error::Other("No home directory found.".into()))?
+                .join(".passage"),
+        };
+
+        let settings = user.merge(store(&store_dir)?.into()).merge(env);
+        let charset: Vec<char> = match settings.generator.charset {
+            Some(charset) => charset.chars().collect(),
+            None => ('!'..='~').collect(),
+        };
+        if charset.is_empty() {
+            return Err(Error::Other(
+                "The character set of generated passwords is empty.".into(),
+            ));
+        }
+
+        let recipients = settings
+            .recipients
+            .unwrap_or_default()
+            .iter()
+            .map(|r| {
+                r.parse()
+                    .map_err(|e| Error::Other(format!("Invalid recipient {:?}: {}", r, e)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let docker_prefix = match settings.docker_prefix {
+            Some(prefix) => {
+                let prefix = prefix.trim_matches('/');
+                ItemName::new(prefix).map_err(|e| {
+                    Error::Other(format!("Invalid docker-prefix {:?}: {}", prefix, e))
+                })?;
+                prefix.to_string()
+            }
+            None => "docker".into(),
+        };
+
+        Ok(Config {
+            store_dir,
+            identities: match settings.identity {
+                Some(identity) => identity.expand(),
+                None => key::secret_key_path().into_iter().collect(),
+            },
+            clip_timeout: settings.clip_timeout.unwrap_or(45),
+            length: settings.generator.length.unwrap_or(25),
+            charset,
+            editor: settings.editor.filter(|editor| !editor.trim().is_empty()),
+            color: settings.color.unwrap_or(Color::Auto),
+            git_auto_commit: settings.git_auto_commit.unwrap_or(false),
+            recipients,
+            lock_memory: settings.lock_memory.unwrap_or(false),
+            docker_prefix,
+            profile: None,
+            stores_file: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse<T: DeserializeOwned>(toml: &str) -> T {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn store_settings_and_environment_take_precedence() -> Result<(), Error> {
+        let user: Settings = parse(
+            r#"
+            store = "/srv/passage"
+            clip-timeout = 10
+            editor = "vim"
+            color = "never"
+
+            [generator]
+            length = 40
+            charset = "abc"
+            "#,
+        );
+        let store = parse("clip-timeout = 20\ncolor = \"always\"\n");
+        let vars: HashMap<&str, &str> = [("PASSAGE_GENERATED_LENGTH", "12")].into();
+        let env = Settings::from_env(&|name| vars.get(name).map(|v| v.to_string()))?;
+
+        let config = Config::resolve(user, None, env, |dir| {
+            assert_eq!(dir, Path::new("/srv/passage"));
+            Ok(store)
+        })?;
+        assert_eq!(config.clip_timeout, 20);
+        assert_eq!(config.length, 12);
+        assert_eq!(config.charset, vec!['a', 'b', 'c']);
+        assert_eq!(config.editor.as_deref(), Some("vim"));
+        assert_eq!(config.color, Color::Always);
+
+        let vars: HashMap<&str, &str> =
+            [("PASSAGE_IDENTITIES_FILE", "/a/key.txt:/b/key.txt")].into();
+        let env = Settings::from_env(&|name| vars.get(name).map(|v| v.to_string()))?;
+        let user: Settings = parse("identity = \"/c/key.txt\"\n");
+        assert_eq!(
+            user.identity.as_ref().unwrap().expand(),
+            [PathBuf::from("/c/key.txt")]
+        );
+        let config = Config::resolve(user, None, env, |_| Ok(StoreSettings::default()))?;
+        assert_eq!(config.identity()?, Path::new("/a/key.txt"));
+        assert_eq!(config.identities.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stores_only_set_allowed_settings() -> Result<(), Error> {
+        assert!(toml::from_str::<Settings>("unknown = 1\n").is_err());
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(STORE_CONFIG);
+        for setting in &[
+            "recipients = [\"age1...\"]",
+            "editor = \"rm -rf ~\"",
+            "identity = \"/mnt/attacker.txt\"",
+            "store = \"/tmp\"",
+            "git-auto-commit = true",
+            "lock-memory = false",
+            "docker-prefix = \"public\"",
+        ] {
+            fs::write(&path, setting)?;
+            assert!(
+                read_settings::<StoreSettings>(&path).is_err(),
+                "{} should be rejected",
+                setting
+            );
+        }
+
+        fs::write(
+            &path,
+            "clip-timeout = 5\ncolor = \"never\"\n[generator]\nlength = 30\n",
+        )?;
+        let settings: Settings = read_settings::<StoreSettings>(&path)?.into();
+        assert_eq!(settings.clip_timeout, Some(5));
+        assert_eq!(settings.generator.length, Some(30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn docker_prefix_is_an_item_name() -> Result<(), Error> {
+        let resolve = |toml: &str| {
+            Config::resolve(parse(toml), None, Settings::default(), |_| {
+                Ok(StoreSettings::default())
+            })
+        };
+        assert_eq!(resolve("")?.docker_prefix, "docker");
+        assert_eq!(
+            resolve("docker-prefix = \"/ci/docker/\"")?.docker_prefix,
+            "ci/docker"
+        );
+        for prefix in &["\"\"", "\"/\"", "\"../docker\"", "\".hidden\""] {
+            assert!(resolve(&format!("docker-prefix = {}", prefix)).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn registered_stores() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(STORES_FILE);
+
+        let mut stores = Stores::read(&path)?;
+        stores.add(
+            "work",
+            Path::new("/srv/work"),
+            &[PathBuf::from("/mnt/key.txt")],
+        )?;
+        stores.add("personal", Path::new("/home/me/.passage"), &[])?;
+        assert!(stores.add("work", Path::new("/tmp"), &[]).is_err());
+        assert!(stores.add("no spaces", Path::new("/tmp"), &[]).is_err());
+        stores.save()?;
+
+        let mut stores = Stores::read(&path)?;
+        let names: Vec<&str> = stores.list().map(|(name, _)| name).collect();
+        assert_eq!(names, ["personal", "work"]);
+        assert_eq!(stores.remove("personal")?, Path::new("/home/me/.passage"));
+        assert!(stores.remove("personal").is_err());
+
+        let user: Settings = parse("store = \"/home/me/.passage\"\nclip-timeout = 10\n");
+        let profile = Some(stores.take("work")?);
+        let config = Config::resolve(user, profile, Settings::default(), |dir| {
+            assert_eq!(dir, Path::new("/srv/work"));
+            Ok(StoreSettings::default())
+        })?;
+        assert_eq!(config.identities, [PathBuf::from("/mnt/key.txt")]);
+        assert_eq!(config.clip_timeout, 10);
+
+        Ok(())
+    }
+}
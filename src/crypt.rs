@@ -1,10 +1,135 @@
+//! Encryption and decryption with age keys and passphrases.
+//!
+//! Decrypted data is returned in buffers that are zeroed when dropped, so that secrets do not
+//! linger in freed memory.
+
 use age::x25519::{Identity, Recipient};
-use secrecy::Secret;
-use std::io::prelude::*;
+use secrecy::{ExposeSecret, SecretString};
+use std::io::{self, prelude::*, BufReader};
 use std::iter;
+use zeroize::Zeroizing;
 
 use crate::error::Error;
-use crate::input;
+
+/// Largest scrypt work factor accepted when decrypting with a passphrase.
+///
+/// age by default only accepts work factors up to roughly 16 times the time it takes to
+/// encrypt on the current machine, which would reject keys locked with a deliberately high
+/// work factor (see `passage lock --work-factor`).
+pub const MAX_WORK_FACTOR: u8 = 22;
+
+/// A buffer for the plaintext of a ciphertext of `len` bytes.
+///
+/// The plaintext is never longer than its ciphertext, so the buffer never has to grow, which would
+/// leave copies of the plaintext behind in freed memory.
+pub(crate) fn plaintext_buffer(len: usize) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(Vec::with_capacity(len))
+}
+
+/// Encrypt `plaintext` with a passphrase.
+///
+/// The passphrase is stretched with scrypt using `work_factor` (the base 2 logarithm of the scrypt
+/// N parameter), or a work factor that takes about one second on this machine if `None`.
+pub fn encrypt_with_passphrase(
+    plaintext: &str,
+    passphrase: &SecretString,
+    work_factor: Option<u8>,
+) -> Result<Vec<u8>, Error> {
+    let mut recipient = age::scrypt::Recipient::new(passphrase.expose_secret().into());
+    if let Some(log_n) = work_factor {
+        recipient.set_work_factor(log_n);
+    }
+
+    let encryptor = age::Encryptor::with_recipients(iter::once(&recipient as &dyn age::Recipient))?;
+    let mut encrypted = vec![];
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(plaintext.as_bytes())?;
+    writer.finish()?;
+
+    Ok(encrypted)
+}
+
+/// Decrypt a ciphertext produced by [`encrypt_with_passphrase`].
+///
+/// Fails with [`Error::KeyNotEncrypted`] if `cypher` is not an age file.
+pub fn decrypt_with_passphrase(
+    cypher: &[u8],
+    passphrase: &SecretString,
+) -> Result<SecretString, Error> {
+    let decryptor = match age::Decryptor::new(cypher) {
+        Ok(d) if d.is_scrypt() => d,
+        Ok(_) => return Err(age::DecryptError::DecryptionFailed.into()),
+        Err(_) => return Err(Error::KeyNotEncrypted),
+    };
+
+    let mut identity = age::scrypt::Identity::new(passphrase.expose_secret().into());
+    identity.set_max_work_factor(MAX_WORK_FACTOR);
+
+    let mut decrypted = plaintext_buffer(cypher.len());
+    let mut reader = decryptor.decrypt(iter::once(&identity as &dyn age::Identity))?;
+    reader.read_to_end(&mut decrypted)?;
+
+    match std::str::from_utf8(&decrypted) {
+        Ok(e) => Ok(e.into()),
+        Err(_) => Err(age::DecryptError::DecryptionFailed.into()),
+    }
+}
+
+/// Encrypt `plaintext` to each of the given recipients.
+pub fn encrypt_with_keys(plaintext: &[u8], recipients: &[Recipient]) -> Result<Vec<u8>, Error> {
+    let mut encrypted = vec![];
+    encrypt_stream(plaintext, &mut encrypted, recipients)?;
+
+    Ok(encrypted)
+}
+
+/// Decrypt a ciphertext produced by [`encrypt_with_keys`] with the secret key of one of its
+/// recipients.
+pub fn decrypt_with_key(cypher: &[u8], key: &Identity) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let mut decrypted = plaintext_buffer(cypher.len());
+    decrypt_stream(cypher, &mut *decrypted, key)?;
+
+    Ok(decrypted)
+}
+
+/// Encrypt everything read from `plaintext` to each of the given recipients, writing the
+/// ciphertext to `output` as it is produced rather than holding it in memory.
+pub fn encrypt_stream<R: Read, W: Write>(
+    mut plaintext: R,
+    output: W,
+    recipients: &[Recipient],
+) -> Result<(), Error> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+
+    let mut writer = encryptor.wrap_output(output)?;
+    io::copy(&mut plaintext, &mut writer)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Decrypt a ciphertext read from `cypher` with the secret key of one of its recipients, writing
+/// the plaintext to `output` as it is produced.
+///
+/// age authenticates the plaintext in chunks, so if an error is returned part of the plaintext
+/// may already have been written and must be discarded.
+pub fn decrypt_stream<R: Read, W: Write>(
+    cypher: R,
+    mut output: W,
+    key: &Identity,
+) -> Result<(), Error> {
+    let decryptor = match age::Decryptor::new_buffered(BufReader::new(cypher)) {
+        Ok(d) if d.is_scrypt() => return Err(age::DecryptError::KeyDecryptionFailed.into()),
+        Ok(d) => d,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut reader = decryptor.decrypt(iter::once(key as &dyn age::Identity))?;
+    io::copy(&mut reader, &mut output)?;
+
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -16,96 +141,59 @@ mod tests {
         let key = Identity::generate();
         let pubkey = key.to_public();
 
-        let encrypted = encrypt_with_keys(plaintext, &[pubkey])?;
+        let encrypted = encrypt_with_keys(plaintext.as_bytes(), &[pubkey])?;
         let decrypted = decrypt_with_key(&encrypted, &key)?;
 
-        assert_eq!(decrypted, plaintext);
+        assert_eq!(*decrypted, plaintext.as_bytes());
 
         Ok(())
     }
 
     #[test]
-    fn encrypt_and_decrypt_with_passphrase() -> Result<(), Error> {
-        let plaintext = "Testing encrypt_and_decrypt_with_passphrase";
-        let passphrase = "correct horse battery staple";
-
-        let encrypted = encrypt_with_passphrase(plaintext, passphrase)?;
-        let decrypted = decrypt_with_passphrase(&encrypted, Some(passphrase))?;
+    fn streaming_large_plaintexts() -> Result<(), Error> {
+        let key = Identity::generate();
+        // Several of age's 64 KiB chunks
+        let plaintext: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
 
+        let mut encrypted = vec![];
+        encrypt_stream(&plaintext[..], &mut encrypted, &[key.to_public()])?;
+        let mut decrypted = vec![];
+        decrypt_stream(&encrypted[..], &mut decrypted, &key)?;
         assert_eq!(decrypted, plaintext);
 
+        // Truncated ciphertext is detected
+        encrypted.truncate(encrypted.len() - 1000);
+        assert!(decrypt_stream(&encrypted[..], io::sink(), &key).is_err());
+
         Ok(())
     }
-}
-
-pub fn encrypt_with_passphrase(plaintext: &str, passphrase: &str) -> Result<Vec<u8>, Error> {
-    let encryptor = age::Encryptor::with_user_passphrase(Secret::new(passphrase.to_owned()));
-    let mut encrypted = vec![];
-    let mut writer = encryptor.wrap_output(&mut encrypted)?;
-    writer.write_all(plaintext.as_bytes())?;
-    writer.finish()?;
-
-    Ok(encrypted)
-}
 
-pub fn decrypt_with_passphrase(cypher: &[u8], passphrase: Option<&str>) -> Result<String, Error> {
-    let decryptor = match age::Decryptor::new(cypher) {
-        Ok(d) => match d {
-            age::Decryptor::Passphrase(decryptor) => decryptor,
-            _ => return Err(age::DecryptError::DecryptionFailed.into()),
-        },
-        _ => return Err(Error::KeyNotEncrypted),
-    };
+    #[test]
+    fn encrypt_and_decrypt_with_passphrase() -> Result<(), Error> {
+        let plaintext = "Testing encrypt_and_decrypt_with_passphrase";
+        let passphrase = SecretString::from("correct horse battery staple");
 
-    let passphrase = match passphrase {
-        Some(s) => s.to_owned(),
-        None => input::read_secret("Passphrase", None)?,
-    };
+        let encrypted = encrypt_with_passphrase(plaintext, &passphrase, None)?;
+        let decrypted = decrypt_with_passphrase(&encrypted, &passphrase)?;
 
-    let mut decrypted = vec![];
-    let mut reader = decryptor.decrypt(&Secret::new(passphrase), None)?;
-    reader.read_to_end(&mut decrypted)?;
+        assert_eq!(decrypted.expose_secret(), plaintext);
 
-    match String::from_utf8(decrypted) {
-        Ok(e) => Ok(e),
-        Err(_) => Err(age::DecryptError::DecryptionFailed.into()),
+        Ok(())
     }
-}
-
-pub fn encrypt_with_keys(plaintext: &str, recipients: &[Recipient]) -> Result<Vec<u8>, Error> {
-    let encryptor = age::Encryptor::with_recipients(
-        recipients
-            .iter()
-            .map(|r| Box::new(r.to_owned()) as Box<dyn age::Recipient>)
-            .collect(),
-    );
 
-    let mut encrypted = vec![];
-    let mut writer = encryptor.wrap_output(&mut encrypted)?;
-    writer.write_all(plaintext.as_bytes())?;
-    writer.finish()?;
+    #[test]
+    fn encrypt_with_passphrase_work_factor() -> Result<(), Error> {
+        let plaintext = "Testing encrypt_with_passphrase_work_factor";
+        let passphrase = SecretString::from("correct horse battery staple");
 
-    Ok(encrypted)
-}
+        let encrypted = encrypt_with_passphrase(plaintext, &passphrase, Some(10))?;
+        assert!(String::from_utf8_lossy(&encrypted).contains("-> scrypt "));
+        assert!(String::from_utf8_lossy(&encrypted).contains(" 10\n"));
 
-pub fn decrypt_with_key(cypher: &[u8], key: &Identity) -> Result<String, Error> {
-    let decryptor = {
-        match age::Decryptor::new(cypher) {
-            Ok(d) => match d {
-                age::Decryptor::Recipients(d) => d,
-                _ => return Err(age::DecryptError::KeyDecryptionFailed.into()),
-            },
-            Err(e) => return Err(e.into()),
-        }
-    };
+        let decrypted = decrypt_with_passphrase(&encrypted, &passphrase)?;
 
-    let mut decrypted = vec![];
-    let mut reader = decryptor.decrypt(iter::once(key as &dyn age::Identity))?;
-
-    reader.read_to_end(&mut decrypted)?;
+        assert_eq!(decrypted.expose_secret(), plaintext);
 
-    match String::from_utf8(decrypted) {
-        Ok(e) => Ok(e),
-        Err(_) => Err(age::DecryptError::KeyDecryptionFailed.into()),
+        Ok(())
     }
 }
@@ -0,0 +1,236 @@
+//! Structured items.
+//!
+//! An item is stored as plain text, following the convention of pass: the first line is the
+//! password and any following lines of the form `key: value` are fields. Some fields are
+//! recognized (`username`, `url` and `tags`); other fields are kept as custom fields, and the
+//! remaining lines as notes.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use zeroize::Zeroize;
+
+/// The contents of an item, split into its parts. Every part is zeroed when the entry is dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    /// The password, from the first line.
+    pub password: String,
+    /// The `username` field, also recognized as `user` or `login`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// The `url` field, also recognized as `website`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The comma separated `tags` field.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Any other fields, in the order they appear.
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_fields"
+    )]
+    pub fields: Vec<(String, String)>,
+    /// Every line after the first that is not a field.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+}
+
+fn serialize_fields<S: Serializer>(
+    fields: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(fields.len()))?;
+    for (key, value) in fields {
+        map.serialize_entry(key, value)?;
+    }
+    map.end()
+}
+
+impl Entry {
+    /// An entry with only a password.
+    pub fn new(password: &str) -> Entry {
+        let mut entry = Entry::default();
+        entry.password.push_str(password);
+        entry
+    }
+
+    /// Split the text of an item into its parts.
+    pub fn parse(text: &str) -> Entry {
+        let mut lines = text.lines();
+        let mut entry = Entry::new(lines.next().unwrap_or(""));
+        let mut notes = vec![];
+
+        for line in lines {
+            match line.split_once(':') {
+                Some((key, value))
+                    if is_key(key) && (value.is_empty() || value.starts_with(' ')) =>
+                {
+                    entry.set(key, value.trim())
+                }
+                _ => notes.push(line),
+            }
+        }
+
+        entry.notes = notes.join("\n");
+        entry
+    }
+
+    /// Set a field. The first `username` and `url` fields are stored in their own members and tags
+    /// are added to any existing ones. Anything else is added as a custom field.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match key.to_lowercase().as_str() {
+            "username" | "user" | "login" if self.username.is_none() => {
+                self.username = Some(value.to_string())
+            }
+            "url" | "website" if self.url.is_none() => self.url = Some(value.to_string()),
+            "tags" => self.tags.extend(
+                value
+                    .split(',')
+                    .map(|tag| tag.trim())
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| tag.to_string()),
+            ),
+            _ => self.fields.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// The value of a field, looked up case-insensitively. `password` and the recognized fields
+    /// can be looked up too.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key.to_lowercase().as_str() {
+            "password" => Some(self.password.clone()),
+            "username" | "user" | "login" => self.username.clone(),
+            "url" | "website" => self.url.clone(),
+            "tags" if !self.tags.is_empty() => Some(self.tags.join(", ")),
+            "notes" if !self.notes.is_empty() => Some(self.notes.clone()),
+            _ => self
+                .fields
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone()),
+        }
+    }
+}
+
+impl Zeroize for Entry {
+    fn zeroize(&mut self) {
+        self.password.zeroize();
+        self.username.zeroize();
+        self.url.zeroize();
+        self.tags.zeroize();
+        self.fields.iter_mut().for_each(|(key, value)| {
+            key.zeroize();
+            value.zeroize();
+        });
+        self.fields.clear();
+        self.notes.zeroize();
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+/// Whether the text before a colon looks like a field name rather than part of a note, and so
+/// whether `key` can be used as the name of a field.
+pub fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ')
+        && !key.starts_with(' ')
+}
+
+/// Formats the entry as the text of an item, from which [`Entry::parse`] gives back the same entry.
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.password)?;
+        if let Some(username) = &self.username {
+            write!(f, "\nusername: {}", username)?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, "\nurl: {}", url)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, "\ntags: {}", self.tags.join(", "))?;
+        }
+        for (key, value) in &self.fields {
+            write!(f, "\n{}: {}", key, value)?;
+        }
+        if !self.notes.is_empty() {
+            write!(f, "\n{}", self.notes)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_pass_entries() {
+        let entry = Entry::parse(
+            "hunter2\nLogin: alice\nURL: https://example.com\ntags: work, email\n\
+             PIN: 1234\nRecovery codes follow\nhttps://example.com/recover",
+        );
+
+        assert_eq!(entry.password, "hunter2");
+        assert_eq!(entry.username.as_deref(), Some("alice"));
+        assert_eq!(entry.url.as_deref(), Some("https://example.com"));
+        assert_eq!(entry.tags, vec!["work", "email"]);
+        assert_eq!(entry.fields, vec![("PIN".into(), "1234".into())]);
+        assert_eq!(
+            entry.notes,
+            "Recovery codes follow\nhttps://example.com/recover"
+        );
+        assert_eq!(entry.get("pin").as_deref(), Some("1234"));
+
+        assert_eq!(Entry::parse(""), Entry::new(""));
+        assert_eq!(
+            Entry::parse("only a password"),
+            Entry::new("only a password")
+        );
+    }
+
+    #[test]
+    fn serializing_round_trips() {
+        let mut entry = Entry::new("hunter2");
+        entry.set("username", "alice");
+        entry.set("tags", "a, b");
+        entry.set("security question", "blue");
+        entry.notes = "some notes".into();
+
+        assert_eq!(
+            entry.to_string(),
+            "hunter2\nusername: alice\ntags: a, b\nsecurity question: blue\nsome notes"
+        );
+        assert_eq!(Entry::parse(&entry.to_string()), entry);
+    }
+
+    #[test]
+    fn zeroizing_clears_every_part() {
+        let mut entry = Entry::parse("hunter2\nusername: alice\ntags: a\nPIN: 1234\nnotes");
+        entry.zeroize();
+        assert_eq!(entry, Entry::default());
+    }
+
+    #[test]
+    fn field_names() {
+        for key in &[
+            "username",
+            "PIN",
+            "security-question",
+            "api_key",
+            "recovery code",
+        ] {
+            assert!(is_key(key), "{:?} should be a key", key);
+        }
+        for key in &["", " leading", "a:b", "new\nline", "https://example.com"] {
+            assert!(!is_key(key), "{:?} should not be a key", key);
+        }
+    }
+}
@@ -1,16 +1,49 @@
+//! The error type used throughout passage.
+
 use std::error;
 use std::fmt;
 use std::io;
 
+/// Errors returned by passage.
 #[derive(Debug)]
 pub enum Error {
+    /// The named item does not exist.
     ItemNotFound(String),
+    /// The named item already exists.
     ItemAlreadyExists(String),
+    /// An item name was rejected, for the given reason.
+    InvalidItemName(String, &'static str),
+    /// There is no password store.
     StoreNotInitialized,
+    /// There is no secret key.
     NoSecretKey,
+    /// No data directory was found to keep the secret key in.
+    NoDataDir,
+    /// A secret key already exists and would be overwritten.
     SecretKeyExists,
+    /// The secret key is not locked with a passphrase.
     KeyNotEncrypted,
+    /// The secret key is already locked with a passphrase.
+    KeyEncrypted,
+    /// A password and its confirmation differ.
     PasswordsDoNotMatch,
+    /// No way to ask the user for a secret was found.
+    NoPinentry,
+    /// The store can be modified by users other than the current user.
+    InsecurePermissions,
+    /// The store hides its item names or keeps a manifest, and has not been unlocked with
+    /// [`PasswordStore::unlock`](crate::PasswordStore::unlock).
+    SecretKeyRequired,
+    /// The named item is not text.
+    NotText(String),
+    /// The manifest of the store was not signed with the owner's key.
+    InvalidManifest,
+    /// The named item or metadata differs from what the store's manifest records.
+    Tampered(String),
+    /// The manifest of the store belongs to another store or was signed with another key than the
+    /// one seen before.
+    UntrustedManifest,
+    /// Any other error, described by its message.
     Other(String),
 }
 
@@ -21,15 +54,56 @@ impl fmt::Display for Error {
             Error::ItemAlreadyExists(item) => {
                 write!(f, "{} already exists in the password store.", item)
             }
+            Error::InvalidItemName(item, reason) => {
+                write!(f, "{:?} is not a valid item name: {}.", item, reason)
+            }
             Error::StoreNotInitialized => {
                 write!(f, "Password store is empty. Try \"passage init\".")
             }
             Error::NoSecretKey => write!(f, "No secret key found. Try \"passage init\"."),
+            Error::NoDataDir => write!(
+                f,
+                "No data directory found for the secret key. Set PASSAGE_IDENTITIES_FILE."
+            ),
             Error::SecretKeyExists => {
                 write!(f, "Secret key already exists. Use --force to overwrite.")
             }
             Error::KeyNotEncrypted => write!(f, "Password store is not encrypted."),
+            Error::KeyEncrypted => write!(
+                f,
+                "Password store is already locked. Use \"passage passwd\" to change the passphrase."
+            ),
             Error::PasswordsDoNotMatch => write!(f, "Passwords do not match."),
+            Error::NoPinentry => write!(
+                f,
+                "No terminal available and no pinentry program found. Set PINENTRY_PROGRAM."
+            ),
+            Error::InsecurePermissions => write!(
+                f,
+                "Refusing to use a password store that can be modified by other users."
+            ),
+            Error::SecretKeyRequired => write!(
+                f,
+                "The secret key is needed to read or modify this password store."
+            ),
+            Error::NotText(item) => write!(
+                f,
+                "{} is not text. Use \"passage show --output FILE\" to save it to a file.",
+                item
+            ),
+            Error::InvalidManifest => write!(
+                f,
+                "The manifest of the password store has been tampered with. Run \"passage verify\" for details."
+            ),
+            Error::Tampered(what) => write!(
+                f,
+                "{} has been modified by someone else. Run \"passage verify\" for details.",
+                what
+            ),
+            Error::UntrustedManifest => write!(
+                f,
+                "The manifest of the password store was replaced. If the store was recreated on purpose, run \"passage verify --trust\" to accept it."
+            ),
             Error::Other(msg) => write!(f, "{}", msg),
         }
     }
@@ -59,12 +133,27 @@ impl From<age::EncryptError> for Error {
     }
 }
 
+#[cfg(feature = "cli")]
 impl From<pinentry::Error> for Error {
     fn from(e: pinentry::Error) -> Self {
         Error::Other(e.to_string())
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+
 impl From<String> for Error {
     fn from(e: String) -> Self {
         Error::Other(e)
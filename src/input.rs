@@ -1,14 +1,41 @@
-use std::io;
+use pinentry::PassphraseInput;
+use secrecy::{ExposeSecret, SecretString};
+use std::env;
 use std::io::prelude::*;
+use std::io::{self, IsTerminal};
+use zeroize::Zeroizing;
 
-use crate::error::Error;
+use passage::Error;
 
-pub fn read_secret(prompt: &str, confirm: Option<&str>) -> Result<String, Error> {
-    let input = rpassword::prompt_password_stdout(&format!("{}: ", prompt))?;
+fn pinentry<'a>() -> Option<PassphraseInput<'a>> {
+    match env::var("PINENTRY_PROGRAM") {
+        Ok(program) => PassphraseInput::with_binary(program),
+        Err(_) => PassphraseInput::with_default_binary(),
+    }
+}
+
+fn read_secret_pinentry<'a>(
+    mut input: PassphraseInput<'a>,
+    prompt: &'a str,
+    confirm: Option<&'a str>,
+) -> Result<SecretString, Error> {
+    input.with_title("passage").with_description(prompt);
+
+    if let Some(confirm) = confirm {
+        input.with_confirmation(confirm, "Passwords do not match.");
+    }
+
+    Ok(input.interact()?)
+}
+
+fn read_secret_tty(prompt: &str, confirm: Option<&str>) -> Result<SecretString, Error> {
+    let input = SecretString::from(rpassword::prompt_password_stdout(&format!("{}: ", prompt))?);
 
     match confirm {
         Some(prompt) => {
-            if rpassword::prompt_password_stdout(&format!("{}: ", prompt))? != input {
+            let again =
+                Zeroizing::new(rpassword::prompt_password_stdout(&format!("{}: ", prompt))?);
+            if *again != input.expose_secret() {
                 Err(Error::PasswordsDoNotMatch)
             } else {
                 Ok(input)
@@ -18,6 +45,24 @@ pub fn read_secret(prompt: &str, confirm: Option<&str>) -> Result<String, Error>
     }
 }
 
+/// Prompt the user for a secret, optionally asking a second time for confirmation.
+///
+/// If a pinentry program can be found (either `$PINENTRY_PROGRAM` or `pinentry` in `$PATH`) it is
+/// used to read the secret, with `prompt` as the dialog description. Otherwise the secret is read
+/// from the terminal.
+pub fn read_secret(prompt: &str, confirm: Option<&str>) -> Result<SecretString, Error> {
+    match pinentry() {
+        Some(input) => read_secret_pinentry(input, prompt, confirm),
+        None if io::stdin().is_terminal() => read_secret_tty(prompt, confirm),
+        None => Err(Error::NoPinentry),
+    }
+}
+
+/// Ask for the passphrase of a locked secret key, see [`passage::Prompt`].
+pub fn prompt(description: &str) -> Result<SecretString, Error> {
+    read_secret(description, None)
+}
+
 pub fn read_input(prompt: &str) -> Result<String, Error> {
     print!("{} ", prompt);
     io::stdout().flush()?;
@@ -0,0 +1,115 @@
+//! Validated item names.
+
+use serde::Serialize;
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// The name of an item in the password store, such as `email/work`.
+///
+/// Item names are paths relative to the store directory. To make sure an item can never refer to
+/// a file outside of the store, names may not be empty, absolute or contain `.` or `..`
+/// components, or hidden (dot-prefixed) components. Names are also kept on a single line in the
+/// index, manifest and re-encryption journal, so they may not contain control characters such as
+/// NUL bytes or newlines.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct ItemName(String);
+
+impl ItemName {
+    /// Validate an item name, failing with [`Error::InvalidItemName`] if it is not allowed.
+    pub fn new(name: &str) -> Result<ItemName, Error> {
+        let invalid = |reason| Err(Error::InvalidItemName(name.into(), reason));
+
+        if name.is_empty() {
+            return invalid("name is empty");
+        }
+
+        if name.contains(char::is_control) {
+            return invalid("name contains a control character");
+        }
+
+        if name.ends_with('/') {
+            return invalid("name ends with a slash");
+        }
+
+        for component in Path::new(name).components() {
+            match component {
+                Component::Normal(s) if s.to_string_lossy().starts_with('.') => {
+                    return invalid("hidden path components are not allowed");
+                }
+                Component::Normal(_) => {}
+                Component::RootDir | Component::Prefix(_) => {
+                    return invalid("name must be relative to the password store");
+                }
+                Component::CurDir | Component::ParentDir => {
+                    return invalid("'.' and '..' path components are not allowed");
+                }
+            }
+        }
+
+        Ok(ItemName(name.into()))
+    }
+
+    /// The item name as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ItemName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ItemName::new(s)
+    }
+}
+
+impl fmt::Display for ItemName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in &[
+            "github",
+            "email/work",
+            "a/b/c",
+            "with space",
+            "dots.in.name",
+        ] {
+            assert_eq!(ItemName::new(name).unwrap().as_str(), *name);
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        for name in &[
+            "",
+            "../../.bashrc",
+            "a/../../b",
+            "/etc/passwd",
+            "./a",
+            ".public-keys",
+            "a/.hidden",
+            "a/",
+            "nul\0byte",
+            "new\nline",
+            "carriage\rreturn",
+            "tab\tname",
+        ] {
+            assert!(
+                matches!(ItemName::new(name), Err(Error::InvalidItemName(..))),
+                "{:?} should be invalid",
+                name
+            );
+        }
+    }
+}
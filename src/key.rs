@@ -1,126 +1,311 @@
-use age::{x25519::Identity, IdentityFile};
-use secrecy::ExposeSecret;
-use std::fs::{self, File, OpenOptions};
+//! Loading, saving, locking and unlocking the secret key.
+
+use age::x25519::{Identity, Recipient};
+use secrecy::{ExposeSecret, SecretString};
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
-#[cfg(unix)]
-use std::os::unix::fs::OpenOptionsExt;
-
+use crate::atomic;
 use crate::crypt;
 use crate::error::Error;
-use crate::input;
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::crypt;
-    use std::env;
-
-    #[test]
-    fn saving_and_reading_secret_key() -> Result<(), Error> {
-        let plaintext = "Testing saving_and_reading_secret_key";
-        let key = Identity::generate();
-        let path = env::temp_dir().join("key.txt");
-        let encrypted = crypt::encrypt_with_keys(plaintext, &[key.to_public()])?;
-
-        save_secret_key(&key, &path, true)?;
+use crate::perms;
 
-        let key = read_secret_key(&path)?;
-        let decrypted = crypt::decrypt_with_key(&encrypted, &key)?;
-
-        assert_eq!(decrypted, plaintext);
+/// Asks the user for the passphrase of a locked secret key.
+///
+/// Functions loading a secret key call back into this trait only when the key turns out to be
+/// encrypted, so that applications can decide how (and whether) to interact with the user. It is
+/// implemented for closures taking a description of what the passphrase is for.
+pub trait Prompt {
+    /// Ask for a passphrase. `description` explains what the passphrase is needed for.
+    fn passphrase(&self, description: &str) -> Result<SecretString, Error>;
+}
 
-        Ok(())
+impl<F> Prompt for F
+where
+    F: Fn(&str) -> Result<SecretString, Error>,
+{
+    fn passphrase(&self, description: &str) -> Result<SecretString, Error> {
+        self(description)
     }
 }
 
-pub fn secret_key_path() -> PathBuf {
-    dirs::data_dir().unwrap().join("passage").join("key.txt")
+/// The default location of the secret key, `$XDG_DATA_HOME/passage/key.txt`.
+///
+/// Fails with [`Error::NoDataDir`] if there is no data directory, for instance because `HOME` is
+/// not set.
+pub fn secret_key_path() -> Result<PathBuf, Error> {
+    dirs::data_dir()
+        .map(|dir| dir.join("passage").join("key.txt"))
+        .ok_or(Error::NoDataDir)
 }
 
+/// Write a secret key to `path`, readable only by the current user.
+///
+/// Unless `force` is set, fails with [`Error::SecretKeyExists`] if `path` already exists.
 pub fn save_secret_key<P: AsRef<Path>>(key: &Identity, path: P, force: bool) -> Result<(), Error> {
     let path = path.as_ref();
     if !path.exists() {
-        fs::create_dir_all(path.parent().unwrap())?;
+        perms::create_dir_all(path.parent().unwrap())?;
     }
 
-    let mut options = OpenOptions::new();
-    options.write(true).truncate(true);
-
-    if force {
-        options.create(true);
+    let contents = key.to_string();
+    let contents = contents.expose_secret().as_bytes();
+    let result = if force {
+        atomic::write(path, contents)
     } else {
-        options.create_new(true);
-    }
-
-    #[cfg(unix)]
-    options.mode(0o600);
+        atomic::write_new(path, contents)
+    };
 
-    let mut key_file = match options.open(&path) {
-        Ok(f) => f,
+    match result {
+        Ok(()) => Ok(()),
         Err(e) => match e.kind() {
-            io::ErrorKind::AlreadyExists => return Err(Error::SecretKeyExists),
-            _ => return Err(e.into()),
+            io::ErrorKind::AlreadyExists => Err(Error::SecretKeyExists),
+            _ => Err(e.into()),
         },
-    };
+    }
+}
 
-    key_file.write_all(key.to_string().expose_secret().as_bytes())?;
+/// Parse the secret keys found in the contents of an age identity file.
+fn parse_secret_keys(bytes: &[u8]) -> Result<Vec<Identity>, Error> {
+    let contents = std::str::from_utf8(bytes).map_err(|_| Error::NoSecretKey)?;
+    let keys: Vec<Identity> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.parse().ok())
+        .collect();
 
-    Ok(())
+    if keys.is_empty() {
+        return Err(Error::NoSecretKey);
+    }
+    Ok(keys)
 }
 
-pub fn read_secret_key<P: AsRef<Path>>(path: P) -> Result<Identity, Error> {
+/// Parse the last secret key found in the contents of an age identity file.
+fn parse_secret_key(bytes: &[u8]) -> Result<Identity, Error> {
+    Ok(parse_secret_keys(bytes)?.pop().unwrap())
+}
+
+/// Read the contents of a key file into a buffer that is zeroed when dropped.
+fn read_key_file(path: &Path) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+
+    // Allocate the whole buffer up front so that it never has to grow, leaving copies behind
+    let mut bytes = Zeroizing::new(Vec::with_capacity(len + 1));
+    file.read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/// Whether the secret key at the given path is encrypted with a passphrase.
+pub fn is_locked<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
     let path = path.as_ref();
     if !path.exists() {
         return Err(Error::NoSecretKey);
     }
 
-    match IdentityFile::from_file(path.to_str().unwrap().to_string()) {
-        Ok(identity_file) => identity_file
-            .into_identities()
-            .pop()
-            .ok_or(Error::NoSecretKey),
+    Ok(parse_secret_key(&read_key_file(path)?).is_err())
+}
+
+/// Read every secret key in the identity file at `path`, asking `prompt` for the passphrase if
+/// the file is locked.
+fn read_secret_keys(path: &Path, prompt: &dyn Prompt) -> Result<Vec<Identity>, Error> {
+    if !path.exists() {
+        return Err(Error::NoSecretKey);
+    }
+
+    let bytes = read_key_file(path)?;
+
+    match parse_secret_keys(&bytes) {
+        Ok(keys) => Ok(keys),
         // The key file might be encrypted with a passphrase
         Err(_) => {
-            let mut bytes = vec![];
-            File::open(path)?.read_to_end(&mut bytes)?;
-
-            let passphrase = input::read_secret("Passphrase for secret key", None)?;
-            let decrypted = crypt::decrypt_with_passphrase(&bytes, Some(&passphrase))?;
-            match IdentityFile::from_buffer(decrypted.as_bytes()) {
-                Ok(identity_file) => identity_file
-                    .into_identities()
-                    .pop()
-                    .ok_or(Error::NoSecretKey),
-                Err(e) => Err(e.into()),
-            }
+            let passphrase = prompt.passphrase("Passphrase for secret key")?;
+            let decrypted = crypt::decrypt_with_passphrase(&bytes, &passphrase)?;
+            parse_secret_keys(decrypted.expose_secret().as_bytes())
+        }
+    }
+}
+
+/// Read the secret key stored at `path`. If the file holds several keys, the last one is used.
+///
+/// If the key is locked, `prompt` is asked for the passphrase to decrypt it.
+pub fn read_secret_key<P: AsRef<Path>>(path: P, prompt: &dyn Prompt) -> Result<Identity, Error> {
+    Ok(read_secret_keys(path.as_ref(), prompt)?.pop().unwrap())
+}
+
+/// Read the secret key for a store encrypted to `recipients` from one of several identity files.
+///
+/// The files are read in order, skipping those that do not exist, until one holds the secret key
+/// of one of `recipients`. If none does, the key [`read_secret_key`] would read from the first
+/// existing file is returned. Fails with [`Error::NoSecretKey`] if none of the files exist.
+pub fn find_secret_key<P: AsRef<Path>>(
+    paths: &[P],
+    recipients: &[Recipient],
+    prompt: &dyn Prompt,
+) -> Result<Identity, Error> {
+    let recipients: Vec<String> = recipients.iter().map(|r| r.to_string()).collect();
+    let mut fallback = None;
+
+    for path in paths {
+        let mut keys = match read_secret_keys(path.as_ref(), prompt) {
+            Ok(keys) => keys,
+            Err(Error::NoSecretKey) if !path.as_ref().exists() => continue,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(i) = keys
+            .iter()
+            .position(|key| recipients.contains(&key.to_public().to_string()))
+        {
+            return Ok(keys.swap_remove(i));
+        }
+        if fallback.is_none() {
+            fallback = keys.pop();
         }
     }
+
+    fallback.ok_or(Error::NoSecretKey)
 }
 
-pub fn encrypt_secret_key<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<(), Error> {
-    let key = read_secret_key(&path)?;
-    let encrypted = crypt::encrypt_with_passphrase(&key.to_string().expose_secret(), &passphrase)?;
+/// Lock the secret key stored at `path` by encrypting it with `passphrase`.
+///
+/// `work_factor` overrides the scrypt work factor, see [`crypt::encrypt_with_passphrase`].
+pub fn encrypt_secret_key<P: AsRef<Path>>(
+    path: P,
+    passphrase: &SecretString,
+    work_factor: Option<u8>,
+) -> Result<(), Error> {
+    let key = read_secret_key(&path, &|_: &str| Err(Error::KeyEncrypted))?;
+    let encrypted =
+        crypt::encrypt_with_passphrase(key.to_string().expose_secret(), passphrase, work_factor)?;
 
-    File::create(&path)?.write_all(&encrypted)?;
+    atomic::write(&path, &encrypted)?;
 
     Ok(())
 }
 
-pub fn decrypt_secret_key<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> Result<(), Error> {
-    let mut encrypted = vec![];
-    File::open(&path)?.read_to_end(&mut encrypted)?;
+/// Unlock the secret key stored at `path` by decrypting it with `passphrase`.
+pub fn decrypt_secret_key<P: AsRef<Path>>(path: P, passphrase: &SecretString) -> Result<(), Error> {
+    let encrypted = read_key_file(path.as_ref())?;
 
-    let key = IdentityFile::from_buffer(
-        crypt::decrypt_with_passphrase(&encrypted, passphrase)?.as_bytes(),
-    )?
-    .into_identities()
-    .pop()
-    .ok_or(Error::NoSecretKey)?;
+    let decrypted = crypt::decrypt_with_passphrase(&encrypted, passphrase)?;
+    let key = parse_secret_key(decrypted.expose_secret().as_bytes())?;
 
     save_secret_key(&key, path, true)?;
 
     Ok(())
 }
+
+/// Re-encrypt a locked secret key with a new passphrase.
+///
+/// The key is never written to disk in plaintext: the re-encrypted key atomically replaces the
+/// locked key file.
+pub fn change_passphrase<P: AsRef<Path>>(
+    path: P,
+    old: &SecretString,
+    new: &SecretString,
+    work_factor: Option<u8>,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Err(Error::NoSecretKey);
+    }
+
+    let encrypted = read_key_file(path)?;
+
+    let decrypted = crypt::decrypt_with_passphrase(&encrypted, old)?;
+    let key = parse_secret_key(decrypted.expose_secret().as_bytes())?;
+    let encrypted =
+        crypt::encrypt_with_passphrase(key.to_string().expose_secret(), new, work_factor)?;
+
+    atomic::write(path, &encrypted)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn saving_and_reading_secret_key() -> Result<(), Error> {
+        let plaintext = "Testing saving_and_reading_secret_key";
+        let key = Identity::generate();
+        let path = env::temp_dir().join("key.txt");
+        let encrypted = crypt::encrypt_with_keys(plaintext.as_bytes(), &[key.to_public()])?;
+
+        save_secret_key(&key, &path, true)?;
+
+        let key = read_secret_key(&path, &|_: &str| Err(Error::NoSecretKey))?;
+        let decrypted = crypt::decrypt_with_key(&encrypted, &key)?;
+
+        assert_eq!(*decrypted, plaintext.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn finding_the_key_of_a_store() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let (first, second) = (Identity::generate(), Identity::generate());
+        let missing = dir.path().join("missing.txt");
+        let first_path = dir.path().join("first.txt");
+        let second_path = dir.path().join("second.txt");
+        save_secret_key(&first, &first_path, false)?;
+        fs::write(
+            &second_path,
+            format!(
+                "# two keys\n{}\n{}\n",
+                Identity::generate().to_string().expose_secret(),
+                second.to_string().expose_secret()
+            ),
+        )?;
+        let paths = [&missing, &first_path, &second_path];
+        let prompt = |_: &str| Err(Error::KeyEncrypted);
+
+        let key = find_secret_key(&paths, &[second.to_public()], &prompt)?;
+        assert_eq!(key.to_public().to_string(), second.to_public().to_string());
+
+        let key = find_secret_key(&paths, &[], &prompt)?;
+        assert_eq!(key.to_public().to_string(), first.to_public().to_string());
+
+        assert!(matches!(
+            find_secret_key(&[&missing], &[], &prompt),
+            Err(Error::NoSecretKey)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn changing_passphrase() -> Result<(), Error> {
+        let key = Identity::generate();
+        let path = env::temp_dir().join("locked-key.txt");
+
+        save_secret_key(&key, &path, true)?;
+        let old = SecretString::from("old passphrase");
+        let new = SecretString::from("new passphrase");
+        encrypt_secret_key(&path, &old, Some(10))?;
+        change_passphrase(&path, &old, &new, Some(10))?;
+
+        let encrypted = fs::read(&path)?;
+        assert!(crypt::decrypt_with_passphrase(&encrypted, &old).is_err());
+
+        let decrypted = read_secret_key(&path, &|_: &str| Ok("new passphrase".into()))?;
+
+        assert_eq!(
+            decrypted.to_string().expose_secret(),
+            key.to_string().expose_secret()
+        );
+
+        Ok(())
+    }
+}
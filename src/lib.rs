@@ -0,0 +1,47 @@
+//! A password store built on the age encryption library.
+//!
+//! A [`PasswordStore`] is a directory of items, each encrypted with age to every one of the
+//! store's recipients. Items are decrypted with the secret key of one of those recipients, which
+//! passage keeps in an age identity file that can optionally be locked with a passphrase.
+//!
+//! ```no_run
+//! use passage::{key, Error, ExposeSecret, ItemName, PasswordStore};
+//!
+//! # fn main() -> Result<(), Error> {
+//! let store = PasswordStore::open(dirs::home_dir().unwrap().join(".passage"))?;
+//!
+//! // Only called if the secret key is locked
+//! let prompt = |_: &str| Ok(std::env::var("PASSPHRASE").unwrap_or_default().into());
+//! let key = key::read_secret_key(key::secret_key_path()?, &prompt)?;
+//! store.unlock(&key)?;
+//!
+//! let name: ItemName = "email/work".parse()?;
+//! store.insert(&name, "hunter2")?;
+//! assert_eq!(store.get(&name, &key)?.expose_secret(), "hunter2");
+//! # Ok(())
+//! # }
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod atomic;
+pub mod backend;
+pub mod crypt;
+pub mod entry;
+pub mod error;
+pub mod item;
+pub mod key;
+pub mod manifest;
+pub mod perms;
+pub mod protect;
+pub mod store;
+
+pub use backend::StoreBackend;
+pub use entry::Entry;
+pub use error::Error;
+pub use item::ItemName;
+pub use key::Prompt;
+pub use store::{PasswordStore, Progress};
+
+pub use age::x25519::{Identity, Recipient};
+pub use secrecy::{ExposeSecret, SecretString};
@@ -1,26 +1,90 @@
-use clap::{App, AppSettings, Arg, SubCommand};
-use std::env;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::path::PathBuf;
 use std::process;
 
+use passage::backend::BackendKind;
+use passage::{crypt, entry, protect, Error, PasswordStore};
+
+mod clip;
 mod cmd;
-mod crypt;
-mod error;
+mod config;
 mod input;
-mod key;
-mod store;
+mod pick;
+
+use config::{Color, Config};
+
+fn work_factor_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("work-factor")
+        .help("scrypt work factor used to encrypt the secret key")
+        .short("w")
+        .long("work-factor")
+        .value_name("LOG_N")
+        .takes_value(true)
+        .validator(|s| match s.parse::<u8>() {
+            Ok(n) if n > 0 && n <= crypt::MAX_WORK_FACTOR => Ok(()),
+            _ => Err(format!(
+                "work factor must be between 1 and {}",
+                crypt::MAX_WORK_FACTOR
+            )),
+        })
+}
+
+fn work_factor(matches: &ArgMatches) -> Option<u8> {
+    matches.value_of("work-factor").and_then(|s| s.parse().ok())
+}
+
+/// Open the store in `dir` with the backend it already uses or, if `kind` is given, with that
+/// backend. A store cannot be switched to a different backend once initialized.
+fn open_store(dir: PathBuf, kind: Option<BackendKind>) -> Result<PasswordStore, Error> {
+    let detected = BackendKind::detect(&dir);
+    let kind = match kind {
+        Some(kind) if kind != detected => {
+            if PasswordStore::with_backend(detected.open(dir.clone())?).is_initialized()? {
+                return Err(Error::Other(format!(
+                    "Password store already uses the {} backend.",
+                    detected
+                )));
+            }
+            kind
+        }
+        _ => detected,
+    };
+
+    Ok(PasswordStore::with_backend(kind.open(dir)?))
+}
+
+/// The matches of the command line and of each of its nested subcommands.
+fn subcommands<'a>(matches: &'a ArgMatches<'a>) -> impl Iterator<Item = &'a ArgMatches<'a>> {
+    std::iter::successors(Some(matches), |matches| matches.subcommand().1)
+}
+
+/// Whether a global flag was given, either before or after the subcommand.
+fn is_present(matches: &ArgMatches, name: &str) -> bool {
+    subcommands(matches).any(|matches| matches.is_present(name))
+}
 
-use store::PasswordStore;
+/// The values of a global option, given either before or after the subcommand.
+fn values_of<'a>(matches: &'a ArgMatches<'a>, name: &str) -> Option<clap::Values<'a>> {
+    subcommands(matches).find_map(|matches| matches.values_of(name))
+}
+
+/// Print an error and exit.
+fn fail(e: Error, json: bool) -> ! {
+    if json {
+        eprintln!("{}", serde_json::json!({ "error": e.to_string() }));
+    } else {
+        eprintln!("Error: {}", e);
+    }
+    process::exit(1);
+}
 
 fn main() {
-    let dir = env::var("PASSAGE_STORE_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| dirs::home_dir().unwrap().join(".passage"));
-    let store = PasswordStore::new(dir);
+    if let Err(e) = protect::disable_core_dumps() {
+        eprintln!("Warning: could not disable core dumps: {}", e);
+    }
 
     let matches = App::new(clap::crate_name!())
         .version(clap::crate_version!())
-        .setting(AppSettings::ArgsNegateSubcommands)
         .setting(AppSettings::VersionlessSubcommands)
         .about("Password store built on the age encryption library")
         .long_about(
@@ -42,6 +106,34 @@ Use 'passage help <SUBCOMMAND>' for more information on how to use each subcomma
                 .requires("item")
                 .hidden(true),
         )
+        .arg(
+            Arg::with_name("strict")
+                .help("Refuse to run if the store can be modified by other users")
+                .long("strict")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("store")
+                .help("Use the store registered as NAME with 'passage stores add'")
+                .long("store")
+                .value_name("NAME")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("identity")
+                .help("Read the secret key from FILE (can be repeated)")
+                .long("identity")
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .help("Print output as JSON, for use by other programs")
+                .long("json")
+                .global(true),
+        )
         .arg(
             Arg::with_name("item")
                 .help("Display password for NAME")
@@ -50,7 +142,54 @@ Use 'passage help <SUBCOMMAND>' for more information on how to use each subcomma
         .subcommand(
             SubCommand::with_name("edit")
                 .about("Edit an existing item in the password store")
-                .arg(Arg::with_name("item").value_name("NAME").required(true)),
+                .long_about(
+                    "
+Prompt for a new password for an existing item. If no argument is given, the user is prompted for
+the name of the item to edit.
+
+If an editor is configured (the 'editor' setting or PASSAGE_EDITOR), the decrypted item is opened in
+that editor instead, in a temporary file readable only by you, and saved when the editor exits.
+",
+                )
+                .arg(Arg::with_name("item").value_name("NAME")),
+        )
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Generate a new password")
+                .long_about(
+                    "
+Generate a random password for the given item and insert it into the password store, then display
+it. With -c/--clip, copy the password to the system clipboard instead.
+
+The length of the password (25 by default) can be given as LENGTH or with the 'length' setting of
+the [generator] table in the configuration file. The password is made of the characters of the
+'charset' setting, by default every printable ASCII character other than space.
+
+If the item already exists, the user is prompted to confirm that they wish to overwrite it. To
+bypass confirmation, use the -f/--force flag.
+",
+                )
+                .arg(Arg::with_name("item").value_name("NAME").required(true))
+                .arg(
+                    Arg::with_name("length")
+                        .value_name("LENGTH")
+                        .validator(|s| match s.parse::<usize>() {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err("length must be a positive number".to_string()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("clip")
+                        .help("Copy password to the system clipboard")
+                        .short("c")
+                        .long("clip"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .help("Don't ask before overwriting an existing item")
+                        .short("f")
+                        .long("force"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("init")
@@ -81,8 +220,29 @@ Initialize a new store or reinitialize an existing store using an existing key:
 Add other recipients to your store:
 
     $ passage init -r age1294r5jdje2n2jprxj0avqyvmpsujzlmjt5kla728x5eykgd8cc9skkms53
+
+Use -b/--backend to choose how a new store is kept on disk: 'files' (the default) stores each item
+in its own age file, while 'sqlite' keeps every item in a single SQLite database.
+
+With --hide-names, items are stored under random IDs and their names are kept in an index that is
+encrypted like the items themselves, so that the names of your items are not revealed to anyone
+with access to the store. This cannot be undone.
 ",
                 )
+                .arg(
+                    Arg::with_name("hide-names")
+                        .help("Encrypt the names of the items in the password store")
+                        .long("hide-names"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .help("Storage backend of a new password store")
+                        .short("b")
+                        .long("backend")
+                        .value_name("BACKEND")
+                        .takes_value(true)
+                        .validator(|s| s.parse::<BackendKind>().map(|_| ())),
+                )
                 .arg(
                     Arg::with_name("recipient")
                         .help("Add an additional recipient to the password store")
@@ -105,11 +265,19 @@ Add other recipients to your store:
                 .about("Retrieve a password from the store")
                 .long_about(
                     "
-With no arguments, 'passage show' is equivalent to 'passage ls' or just 'passage'; namely, it lists
-the contents of the store.
+With no arguments, 'passage show' lets you pick the item to display, filtering the items of the
+store as you type (see 'passage pick'). When not run from a terminal, or with --json, it is
+equivalent to 'passage ls' instead.
 
 With an argument, decrypt and display the given item from the store, if it exists. With -c/--clip,
-copy the password to the system clipboard instead.
+copy the password to the system clipboard instead. The clipboard is cleared after 45 seconds, or as
+set by the 'clip-timeout' setting.
+
+With --json, display the item as a JSON object with the fields 'password', 'username', 'url',
+'tags', 'fields' (any other 'key: value' lines) and 'notes' (the remaining lines).
+
+Items that are not text, such as those added with 'passage insert --file', can only be saved to a
+file with -o/--output. Any existing file is replaced with one readable only by you.
 ",
                 )
                 .arg(Arg::with_name("item").value_name("NAME"))
@@ -119,6 +287,16 @@ copy the password to the system clipboard instead.
                         .short("c")
                         .long("clip")
                         .requires("item"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help("Save the item to a file instead of displaying it")
+                        .short("o")
+                        .long("output")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .requires("item")
+                        .conflicts_with("clip"),
                 ),
         )
         .subcommand(
@@ -133,6 +311,23 @@ This command is alternatively called 'list'.
 ",
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("find")
+                .alias("search")
+                .about("List items whose names match a pattern")
+                .long_about(
+                    "
+List the names of all items in the password store containing PATTERN, ignoring case.
+
+This command is alternatively called 'search'.
+",
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .value_name("PATTERN")
+                        .required(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("insert")
                 .alias("add")
@@ -147,6 +342,13 @@ use the -f/--force flag.
 The user is then prompted to enter the password for the new item and then asked again to confirm
 the password.
 
+Additional fields can be stored with the item using -F/--field, which may be given several times.
+Each field is written on its own line after the password as 'key: value', following the convention
+of pass.
+
+With --file, the contents of a file are stored as the item instead, without prompting. The file need
+not be text, so this can be used to store key files and certificates.
+
 This command is alternatively called 'add'.
 ",
                 )
@@ -157,6 +359,36 @@ This command is alternatively called 'add'.
                         .short("f")
                         .long("force")
                         .requires("item"),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .help("Add a field to the item, e.g. username=alice")
+                        .short("F")
+                        .long("field")
+                        .value_name("KEY=VALUE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .validator(|s| match s.split_once('=') {
+                            Some((key, _)) if !entry::is_key(key) => Err(format!(
+                                "{:?} is not a valid field name: use letters, digits, spaces, \
+                                 '-' and '_'",
+                                key
+                            )),
+                            Some((_, value)) if value.contains(['\n', '\r']) => {
+                                Err("field values must fit on a single line".to_string())
+                            }
+                            Some(_) => Ok(()),
+                            None => Err("fields must be given as KEY=VALUE".to_string()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("Store the contents of a file")
+                        .long("file")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .conflicts_with("field"),
                 ),
         )
         .subcommand(
@@ -193,8 +425,27 @@ a password is retrieved from the store (using 'passage show'), the passphrase mu
 
 This can be used to provide an additional measure of security when using systems where other
 users have root access.
+
+The passphrase is stretched using scrypt. By default the work factor is chosen so that unlocking
+takes about one second on the current machine. Use -w/--work-factor to choose a different work
+factor (the base 2 logarithm of the scrypt N parameter).
 ",
-                ),
+                )
+                .arg(work_factor_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("passwd")
+                .about("Change the passphrase of a locked password store")
+                .long_about(
+                    "
+Change the passphrase used to lock the password store. The secret key is decrypted with the current
+passphrase and encrypted again with the new passphrase without ever being written to disk
+unencrypted.
+
+Use -w/--work-factor to change the scrypt work factor at the same time.
+",
+                )
+                .arg(work_factor_arg()),
         )
         .subcommand(
             SubCommand::with_name("unlock")
@@ -205,6 +456,28 @@ Unlock the password store by decrypting the secret key.
 ",
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check the password store for tampering")
+                .long_about(
+                    "
+Compare the password store to its manifest, which records every item and the list of recipients
+along with a version number that increases with every change. The manifest is signed with a key
+encrypted to the store's recipients, so that changes made by anyone else (such as replacing an
+item, adding a recipient or restoring an older copy of the store) are reported.
+
+'passage init' creates the manifest, and accepts the current contents of the store as they are.
+
+If the store was recreated, its manifest no longer matches the one seen before and every command
+reading from the store fails. Use --trust to accept the new manifest.
+",
+                )
+                .arg(
+                    Arg::with_name("trust")
+                        .help("Accept a manifest that replaced the one seen before")
+                        .long("trust"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("key")
                 .about("Display the password store's key")
@@ -221,35 +494,335 @@ With no options, display the public key corresponding to the password store's se
                         .long("secret"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Run a command with items in its environment")
+                .long_about(
+                    "
+Decrypt the given items and run COMMAND with each of them in an environment variable, without ever
+displaying them. Each --env VAR=ITEM sets VAR to the password of ITEM, and --env VAR=ITEM:FIELD to
+the given field of ITEM, such as username or url.
+
+    passage exec --env DB_PASSWORD=prod/db --env DB_USER=prod/db:username -- psql
+",
+                )
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("env")
+                        .help("Set VAR to the password, or the given field, of ITEM")
+                        .short("e")
+                        .long("env")
+                        .value_name("VAR=ITEM[:FIELD]")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .value_name("COMMAND")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inject")
+                .about("Fill in a template with items from the store")
+                .long_about(
+                    "
+Read a template, such as a configuration file, and replace every placeholder of the form
+
+    {{ passage \"ITEM\" }}
+    {{ passage \"ITEM\" field=\"FIELD\" }}
+
+with the password or the given field of ITEM. Other text between {{ and }} is left as it is.
+
+The template is read from the file given with -i/--input, or from standard input. The result is
+written to the file given with -o/--output, replacing it with a file readable only by you, or to
+standard output.
+",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("Read the template from FILE")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help("Write the result to FILE")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("docker-credential")
+                .about("Act as a docker credential helper")
+                .long_about(
+                    "
+Implement the docker credential helper protocol, keeping registry credentials in the store under
+docker/ (or the directory set by the 'docker-prefix' setting). Docker runs this through the
+docker-credential-passage program installed along with passage. To use passage for docker, set
+
+    { \"credsStore\": \"passage\" }
+
+in ~/.docker/config.json.
+",
+                )
+                .arg(
+                    Arg::with_name("operation")
+                        .value_name("OPERATION")
+                        .help("get, store, erase, list or version")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("git-credential")
+                .about("Act as a git credential helper")
+                .long_about(
+                    "
+Implement the git credential helper protocol, keeping the credentials git asks for in the store
+as git/HOST/USERNAME. To use passage for git, run
+
+    git config --global credential.helper '!passage git-credential'
+",
+                )
+                .arg(
+                    Arg::with_name("operation")
+                        .value_name("OPERATION")
+                        .help("get, store or erase")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("native-host")
+                .about("Act as the native messaging host of a browser extension")
+                .long_about(
+                    "
+Answer requests from a browser extension, sent as JSON messages preceded by their length, until
+the browser closes standard input. Browsers run this through the passage-native-host program
+installed along with passage. See passage(1) for the requests and how to register the host with a
+browser.
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pick")
+                .about("Pick an item interactively and print its name")
+                .long_about(
+                    "
+Show the items of the store, filtering them as you type, and print the name of the item chosen.
+Characters typed need only appear in the item's name in order, so 'gh' finds 'sites/github.com'.
+The username, URL and tags of the selected item are shown below the list, along with the names of
+its other fields, if the secret key is not locked.
+
+Use Up/Down or Ctrl-P/Ctrl-N to move, Enter to choose and Esc or Ctrl-C to cancel. The same picker
+is used by 'show', 'edit' and 'rm' when no item is given, and by 'insert', where Tab copies the
+selected name to be edited and Enter uses the name typed.
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stores")
+                .about("Manage the stores registered by name")
+                .long_about(
+                    "
+Several password stores, such as a personal and a work store, can be registered by name. Use
+--store NAME or set PASSAGE_PROFILE to use one of them. Each registered store has its own directory,
+identity files and settings, kept in stores.toml next to the configuration file.
+",
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the registered stores, marking the one in use")
+                        .alias("ls"),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Register the password store in DIR as NAME")
+                        .long_about(
+                            "
+Register the password store in DIR as NAME. Its secret key is read from the files given with
+--identity, if any, or else from the default identity file.
+",
+                        )
+                        .arg(Arg::with_name("name").value_name("NAME").required(true))
+                        .arg(Arg::with_name("dir").value_name("DIR").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Forget the store registered as NAME, leaving its files alone")
+                        .alias("rm")
+                        .arg(Arg::with_name("name").value_name("NAME").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(clip::DAEMON)
+                .setting(AppSettings::Hidden)
+                .arg(Arg::with_name("timeout").required(true)),
+        )
         .get_matches();
 
+    // The process holding the clipboard needs neither the configuration nor the store
+    #[cfg(unix)]
+    if let (clip::DAEMON, Some(sub)) = matches.subcommand() {
+        clip::serve(sub.value_of("timeout").unwrap().parse().unwrap_or(0));
+        return;
+    }
+
+    let backend = match matches.subcommand() {
+        ("init", Some(sub)) => sub.value_of("backend").and_then(|s| s.parse().ok()),
+        _ => None,
+    };
+    let json = is_present(&matches, "json");
+    let profile = values_of(&matches, "store").and_then(|mut values| values.next());
+    let mut config = match Config::load(profile) {
+        Ok(config) => config,
+        Err(e) => fail(e, json),
+    };
+    // Identities given to "stores add" are those of the new store
+    let identities = match matches.subcommand() {
+        ("stores", _) => None,
+        _ => values_of(&matches, "identity"),
+    };
+    if let Some(identities) = identities {
+        config.identities = identities.map(PathBuf::from).collect();
+    }
+
+    match config.color {
+        Color::Always => colored::control::set_override(true),
+        Color::Never => colored::control::set_override(false),
+        Color::Auto => {}
+    }
+    if config.lock_memory {
+        if let Err(e) = protect::lock_memory() {
+            eprintln!("Warning: could not lock memory: {}", e);
+        }
+    }
+
+    let store = match open_store(config.store_dir.clone(), backend) {
+        Ok(store) => store,
+        Err(e) => fail(e, json),
+    };
+
+    let problems = store.check_permissions(&config.identities);
+    for problem in &problems {
+        eprintln!("Warning: {}", problem);
+    }
+
+    let strict = is_present(&matches, "strict");
+
     let result = match matches.subcommand() {
+        _ if strict && !problems.is_empty() => Err(Error::InsecurePermissions),
         ("show", Some(sub)) => match sub.value_of("item") {
-            Some(item) => cmd::show(store, item, sub.is_present("clip")),
-            None => cmd::list(store),
+            None if json || !pick::available() => cmd::list(store, &config, json),
+            item => cmd::show(
+                store,
+                &config,
+                item,
+                sub.is_present("clip"),
+                json,
+                sub.value_of("output"),
+            ),
         },
-        ("edit", Some(sub)) => cmd::edit(store, sub.value_of("item")),
+        ("pick", Some(_)) => cmd::pick(store, &config),
+        ("edit", Some(sub)) => cmd::edit(store, &config, sub.value_of("item")),
+        ("generate", Some(sub)) => cmd::generate(
+            store,
+            &config,
+            sub.value_of("item").unwrap(),
+            sub.value_of("length").and_then(|s| s.parse().ok()),
+            sub.is_present("clip"),
+            sub.is_present("force"),
+        ),
         ("init", Some(sub)) => {
             let recipients = sub
                 .values_of("recipient")
                 .map(|v| v.map(|s| s.parse()).filter_map(|r| r.ok()).collect());
-            cmd::init(store, recipients, sub.value_of("key").map(|s| s.to_owned()))
+            cmd::init(
+                store,
+                &config,
+                recipients,
+                sub.value_of("key").map(|s| s.to_owned()),
+                sub.is_present("hide-names"),
+            )
+        }
+        ("ls", Some(_)) => cmd::list(store, &config, json),
+        ("find", Some(sub)) => cmd::find(store, &config, sub.value_of("pattern").unwrap(), json),
+        ("lock", Some(sub)) => cmd::lock(&config, work_factor(sub)),
+        ("passwd", Some(sub)) => cmd::passwd(&config, work_factor(sub)),
+        ("unlock", Some(_)) => cmd::unlock(&config),
+        ("verify", Some(sub)) => cmd::verify(store, &config, sub.is_present("trust")),
+        ("key", Some(sub)) => cmd::key(&config, sub.is_present("secret"), json),
+        ("exec", Some(sub)) => cmd::exec(
+            store,
+            &config,
+            &sub.values_of("env")
+                .map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default(),
+            &sub.values_of("command").unwrap().collect::<Vec<_>>(),
+        ),
+        ("inject", Some(sub)) => cmd::inject(
+            store,
+            &config,
+            sub.value_of("input"),
+            sub.value_of("output"),
+        ),
+        ("docker-credential", Some(sub)) => {
+            cmd::docker_credential(store, &config, sub.value_of("operation").unwrap())
+        }
+        ("git-credential", Some(sub)) => {
+            cmd::git_credential(store, &config, sub.value_of("operation").unwrap())
         }
-        ("ls", Some(_)) => cmd::list(store),
-        ("lock", Some(_)) => cmd::lock(),
-        ("unlock", Some(_)) => cmd::unlock(),
-        ("key", Some(sub)) => cmd::key(sub.is_present("secret")),
-        ("insert", Some(sub)) => cmd::insert(store, sub.value_of("item"), sub.is_present("force")),
-        ("rm", Some(sub)) => cmd::remove(store, sub.value_of("item"), sub.is_present("force")),
+        ("native-host", Some(_)) => cmd::native_host(store, &config),
+        ("stores", Some(sub)) => match sub.subcommand() {
+            ("add", Some(sub)) => cmd::add_store(
+                &config,
+                sub.value_of("name").unwrap(),
+                sub.value_of("dir").unwrap(),
+                &values_of(&matches, "identity")
+                    .map(|values| values.map(PathBuf::from).collect::<Vec<_>>())
+                    .unwrap_or_default(),
+            ),
+            ("remove", Some(sub)) => cmd::remove_store(&config, sub.value_of("name").unwrap()),
+            _ => cmd::list_stores(&config, json),
+        },
+        ("insert", Some(sub)) => {
+            let fields: Vec<(&str, &str)> = sub
+                .values_of("field")
+                .map(|v| v.filter_map(|s| s.split_once('=')).collect())
+                .unwrap_or_default();
+            cmd::insert(
+                store,
+                &config,
+                sub.value_of("item"),
+                sub.is_present("force"),
+                &fields,
+                sub.value_of("file"),
+            )
+        }
+        ("rm", Some(sub)) => cmd::remove(
+            store,
+            &config,
+            sub.value_of("item"),
+            sub.is_present("force"),
+        ),
         ("", None) => match matches.value_of("item") {
-            Some(item) => cmd::show(store, item, matches.is_present("clip")),
-            None => cmd::list(store),
+            Some(item) => cmd::show(
+                store,
+                &config,
+                Some(item),
+                matches.is_present("clip"),
+                json,
+                None,
+            ),
+            None => cmd::list(store, &config, json),
         },
         _ => unreachable!(),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        process::exit(1);
+        fail(e, json);
     }
 }
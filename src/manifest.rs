@@ -0,0 +1,441 @@
+//! Detecting tampering with a password store.
+//!
+//! Since items are encrypted to public keys, anyone who can write to a store can replace an item
+//! with ciphertext of their choosing, add a recipient, or roll the whole store back to an older
+//! state. To detect this, a store can keep a manifest listing a hash of every item and of the
+//! store's metadata, along with a version number that increases with every change. The manifest
+//! is authenticated with a random key that is encrypted to the store's recipients, so that every
+//! recipient, and nobody else, can update it.
+//!
+//! The highest version seen for each store and the key its manifest was signed with are recorded
+//! locally (see [`check_version`]), so that replacing the manifest with an older, validly signed
+//! one, or with one signed with a new key, is detected as well.
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::item::ItemName;
+use crate::{atomic, perms};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const HEADER: &str = "passage-manifest 1";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The hex-encoded SHA-256 hash of `data`, as recorded in a manifest.
+pub fn hash(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// Wraps a reader or writer, hashing everything that passes through it so that large items can
+/// be recorded or checked without holding them in memory.
+pub struct Hashing<T> {
+    inner: T,
+    hasher: Sha256,
+}
+
+impl<T> Hashing<T> {
+    /// Start hashing what is read from or written to `inner`.
+    pub fn new(inner: T) -> Hashing<T> {
+        Hashing {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The wrapped reader or writer and the hash of everything that passed through it, as
+    /// [`hash`] would compute it.
+    pub fn finish(self) -> (T, String) {
+        (self.inner, to_hex(&self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Something whose contents are recorded in a manifest.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Entry {
+    /// An item, by the name under which the backend keeps it.
+    Item(ItemName),
+    /// A metadata blob, such as `public-keys`.
+    Meta(String),
+}
+
+/// The key authenticating a manifest, shared by the recipients of the store.
+#[derive(Clone)]
+pub struct ManifestKey([u8; 32]);
+
+impl ManifestKey {
+    /// Generate a new random key.
+    pub fn generate() -> ManifestKey {
+        ManifestKey(rand::thread_rng().gen())
+    }
+
+    /// Load a key from its raw bytes, failing with [`Error::InvalidManifest`] if they are not a
+    /// key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ManifestKey, Error> {
+        bytes
+            .try_into()
+            .map(ManifestKey)
+            .map_err(|_| Error::InvalidManifest)
+    }
+
+    /// The raw bytes of the key, to be encrypted to the store's recipients.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// A fingerprint identifying the key without revealing it.
+    pub fn id(&self) -> String {
+        to_hex(&self.mac(b"passage manifest key id").finalize().into_bytes()[..16])
+    }
+
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).unwrap();
+        mac.update(data);
+        mac
+    }
+}
+
+/// The recorded contents of a store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// A random identifier of the store, used to tell stores apart when checking versions.
+    pub store_id: String,
+    /// Incremented every time the store is changed.
+    pub version: u64,
+    /// The hash of every item and metadata blob.
+    pub entries: BTreeMap<Entry, String>,
+    /// The [`ManifestKey::id`] of the key the manifest was verified with, if it was.
+    pub key_id: String,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest::new()
+    }
+}
+
+impl Manifest {
+    /// An empty manifest for a new store.
+    pub fn new() -> Manifest {
+        let id: [u8; 16] = rand::thread_rng().gen();
+        Manifest {
+            store_id: to_hex(&id),
+            version: 0,
+            entries: BTreeMap::new(),
+            key_id: String::new(),
+        }
+    }
+
+    fn body(&self) -> String {
+        let mut body = format!(
+            "{}\nstore {}\nversion {}\n",
+            HEADER, self.store_id, self.version
+        );
+        for (entry, hash) in &self.entries {
+            match entry {
+                Entry::Item(name) => body.push_str(&format!("item {} {}\n", hash, name)),
+                Entry::Meta(key) => body.push_str(&format!("meta {} {}\n", hash, key)),
+            }
+        }
+        body
+    }
+
+    /// Serialize the manifest and authenticate it with `key`.
+    pub fn sign(&self, key: &ManifestKey) -> Vec<u8> {
+        let body = self.body();
+        let mac = key.mac(body.as_bytes()).finalize().into_bytes();
+        format!("{}mac {}\n", body, to_hex(&mac)).into_bytes()
+    }
+
+    /// Parse a manifest, failing with [`Error::InvalidManifest`] unless it was signed with `key`.
+    pub fn verify(data: &[u8], key: &ManifestKey) -> Result<Manifest, Error> {
+        let data = std::str::from_utf8(data).map_err(|_| Error::InvalidManifest)?;
+        let mac_start = data.rfind("\nmac ").ok_or(Error::InvalidManifest)? + 1;
+        let (body, mac) = data.split_at(mac_start);
+
+        let mac = from_hex(mac["mac ".len()..].trim_end()).ok_or(Error::InvalidManifest)?;
+        key.mac(body.as_bytes())
+            .verify_slice(&mac)
+            .map_err(|_| Error::InvalidManifest)?;
+
+        let mut lines = body.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(Error::InvalidManifest);
+        }
+
+        let mut fields = lines.next().unwrap_or_default().splitn(2, ' ');
+        let store_id = match (fields.next(), fields.next()) {
+            (Some("store"), Some(id)) if !id.is_empty() => id.to_string(),
+            _ => return Err(Error::InvalidManifest),
+        };
+        let mut fields = lines.next().unwrap_or_default().splitn(2, ' ');
+        let version = match (fields.next(), fields.next()) {
+            (Some("version"), Some(version)) => {
+                version.parse().map_err(|_| Error::InvalidManifest)?
+            }
+            _ => return Err(Error::InvalidManifest),
+        };
+
+        let mut manifest = Manifest {
+            store_id,
+            version,
+            entries: BTreeMap::new(),
+            key_id: key.id(),
+        };
+        for line in lines {
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("item"), Some(hash), Some(name)) => {
+                    let name = name.parse().map_err(|_| Error::InvalidManifest)?;
+                    manifest.entries.insert(Entry::Item(name), hash.to_string());
+                }
+                (Some("meta"), Some(hash), Some(key)) => {
+                    manifest
+                        .entries
+                        .insert(Entry::Meta(key.to_string()), hash.to_string());
+                }
+                _ => return Err(Error::InvalidManifest),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// The default location of the record of the manifest key and highest manifest version seen for
+/// each store, `$XDG_DATA_HOME/passage/versions`.
+///
+/// Fails with [`Error::NoDataDir`] if there is no data directory.
+pub fn versions_path() -> Result<PathBuf, Error> {
+    dirs::data_dir()
+        .map(|dir| dir.join("passage").join("versions"))
+        .ok_or(Error::NoDataDir)
+}
+
+/// The store id, version and key id of the manifest last seen for each store, keyed by the hash of
+/// the store's local identity.
+type Pins = BTreeMap<String, (String, u64, String)>;
+
+fn read_pins(path: &Path) -> Result<Pins, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let pin = fields.next()?.to_string();
+                let store_id = fields.next()?.to_string();
+                let version = fields.next()?.parse().ok()?;
+                let key_id = fields.next()?.to_string();
+                Some((pin, (store_id, version, key_id)))
+            })
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_pins(path: &Path, pins: &Pins) -> Result<(), Error> {
+    let contents: String = pins
+        .iter()
+        .map(|(pin, (store_id, version, key_id))| {
+            format!("{} {} {} {}\n", pin, store_id, version, key_id)
+        })
+        .collect();
+    if let Some(parent) = path.parent() {
+        perms::create_dir_all(parent)?;
+    }
+    Ok(atomic::write(path, contents.as_bytes())?)
+}
+
+/// Compare a verified `manifest` of the store known locally as `store` (such as its canonical
+/// path) to the manifest last seen for it, as recorded in the file at `path`, and record it if
+/// its version is higher.
+///
+/// Returns a description of the problem if the manifest is older, meaning the store was rolled
+/// back. Fails with [`Error::UntrustedManifest`] if it belongs to another store or was signed
+/// with another key, meaning someone replaced the manifest, until it is accepted with [`trust`].
+pub fn check_version(
+    path: &Path,
+    store: &str,
+    manifest: &Manifest,
+) -> Result<Option<String>, Error> {
+    if let Some((store_id, seen, key_id)) = read_pins(path)?.get(&hash(store.as_bytes())) {
+        if *store_id != manifest.store_id || *key_id != manifest.key_id {
+            return Err(Error::UntrustedManifest);
+        }
+        if *seen > manifest.version {
+            return Ok(Some(format!(
+                "The store was rolled back from version {} to version {}",
+                seen, manifest.version
+            )));
+        }
+        if *seen == manifest.version {
+            return Ok(None);
+        }
+    }
+
+    trust(path, store, manifest)?;
+    Ok(None)
+}
+
+/// Accept a verified `manifest` of the store known locally as `store` as the one to compare later
+/// manifests to, even if it belongs to another store or was signed with another key than the
+/// manifest seen before.
+pub fn trust(path: &Path, store: &str, manifest: &Manifest) -> Result<(), Error> {
+    let mut pins = read_pins(path)?;
+    pins.insert(
+        hash(store.as_bytes()),
+        (
+            manifest.store_id.clone(),
+            manifest.version,
+            manifest.key_id.clone(),
+        ),
+    );
+    write_pins(path, &pins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing_and_verifying() -> Result<(), Error> {
+        let key = ManifestKey::generate();
+        let mut manifest = Manifest::new();
+        manifest.version = 3;
+        manifest
+            .entries
+            .insert(Entry::Item("email/work".parse()?), hash(b"ciphertext"));
+        manifest
+            .entries
+            .insert(Entry::Meta("public-keys".into()), hash(b"age1..."));
+
+        let signed = manifest.sign(&key);
+        manifest.key_id = key.id();
+        assert_eq!(Manifest::verify(&signed, &key)?, manifest);
+
+        let other = ManifestKey::generate();
+        assert!(matches!(
+            Manifest::verify(&signed, &other),
+            Err(Error::InvalidManifest)
+        ));
+
+        let tampered = String::from_utf8(signed)
+            .unwrap()
+            .replace("version 3", "version 4");
+        assert!(matches!(
+            Manifest::verify(tampered.as_bytes(), &key),
+            Err(Error::InvalidManifest)
+        ));
+
+        // Every manifest names its store
+        let body = format!("{}\nversion 3\n", HEADER);
+        let mac = key.mac(body.as_bytes()).finalize().into_bytes();
+        let unnamed = format!("{}mac {}\n", body, to_hex(&mac));
+        assert!(matches!(
+            Manifest::verify(unnamed.as_bytes(), &key),
+            Err(Error::InvalidManifest)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn hashing_streams() -> Result<(), Error> {
+        let data = b"some ciphertext";
+
+        let mut reader = Hashing::new(&data[..]);
+        io::copy(&mut reader, &mut io::sink())?;
+        assert_eq!(reader.finish().1, hash(data));
+
+        let mut writer = Hashing::new(vec![]);
+        writer.write_all(data)?;
+        assert_eq!(writer.finish(), (data.to_vec(), hash(data)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_is_detected() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("versions");
+
+        let mut manifest = Manifest::new();
+        manifest.key_id = ManifestKey::generate().id();
+        manifest.version = 5;
+        assert_eq!(check_version(&path, "/store", &manifest)?, None);
+
+        manifest.version = 4;
+        assert_eq!(
+            check_version(&path, "/store", &manifest)?,
+            Some("The store was rolled back from version 5 to version 4".into())
+        );
+
+        manifest.version = 6;
+        assert_eq!(check_version(&path, "/store", &manifest)?, None);
+        assert_eq!(check_version(&path, "/other", &Manifest::new())?, None);
+
+        // A manifest of another store or signed with another key is rejected until trusted
+        let mut forged = manifest.clone();
+        forged.key_id = ManifestKey::generate().id();
+        forged.version = 7;
+        assert!(matches!(
+            check_version(&path, "/store", &forged),
+            Err(Error::UntrustedManifest)
+        ));
+        let mut fresh = Manifest::new();
+        fresh.key_id = manifest.key_id.clone();
+        fresh.version = 7;
+        assert!(matches!(
+            check_version(&path, "/store", &fresh),
+            Err(Error::UntrustedManifest)
+        ));
+        assert_eq!(check_version(&path, "/store", &manifest)?, None);
+
+        trust(&path, "/store", &forged)?;
+        assert_eq!(check_version(&path, "/store", &forged)?, None);
+        assert!(matches!(
+            check_version(&path, "/store", &manifest),
+            Err(Error::UntrustedManifest)
+        ));
+
+        Ok(())
+    }
+}
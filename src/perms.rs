@@ -0,0 +1,80 @@
+//! Restrictive permissions for the password store and checks against tampering.
+//!
+//! Anyone who can write to the store directory or to `.public-keys` can silently add themselves
+//! as a recipient of every new item, so passage creates its directories accessible only to the
+//! owner and checks on startup that nobody else can modify them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+/// Recursively create a directory and all of its parents, accessible only to the owner.
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    builder.mode(0o700);
+
+    builder.create(path)
+}
+
+/// Describe why the given path could be modified by someone other than the current user, if it
+/// can. Paths that do not exist are not a problem.
+#[cfg(unix)]
+pub fn check<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = path.as_ref();
+    let metadata = fs::metadata(path).ok()?;
+
+    // SAFETY: geteuid is always successful and has no side effects
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        return Some(format!(
+            "{} is not owned by the current user",
+            path.display()
+        ));
+    }
+
+    if metadata.mode() & 0o002 != 0 {
+        Some(format!("{} is world writable", path.display()))
+    } else if metadata.mode() & 0o020 != 0 {
+        Some(format!("{} is group writable", path.display()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn check<P: AsRef<Path>>(_path: P) -> Option<String> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn writable_paths_are_reported() -> io::Result<()> {
+        let dir = env::temp_dir().join("passage-perms");
+        let _ = fs::remove_dir_all(&dir);
+
+        create_dir_all(dir.join("nested"))?;
+        assert_eq!(fs::metadata(dir.join("nested"))?.mode() & 0o777, 0o700);
+        assert_eq!(check(&dir), None);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o770))?;
+        assert!(check(&dir).unwrap().contains("group writable"));
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o707))?;
+        assert!(check(&dir).unwrap().contains("world writable"));
+
+        assert_eq!(check(dir.join("missing")), None);
+
+        Ok(())
+    }
+}
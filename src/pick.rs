@@ -0,0 +1,244 @@
+//! An interactive fuzzy finder for choosing an item.
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{PrintStyledContent, Stylize};
+use crossterm::{cursor, execute, queue, terminal};
+use std::collections::HashMap;
+use std::io::{self, prelude::*, IsTerminal};
+
+use passage::{Error, ItemName};
+
+/// The number of lines kept for the preview of the selected item.
+const PREVIEW_LINES: u16 = 6;
+
+/// Whether the picker can be shown, i.e. the user is at a terminal.
+pub fn available() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Score how well `query` matches `name`, or `None` if it does not. Every character of the query
+/// must appear in the name in order, ignoring case. Characters following the previous match or
+/// starting a word score higher, and shorter names are preferred.
+fn score(query: &str, name: &str) -> Option<i64> {
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+
+    for c in query.to_lowercase().chars() {
+        let i = next + name[next..].iter().position(|&n| n == c)?;
+        score += 1;
+        if i > 0 && next == i {
+            score += 5;
+        }
+        if i == 0 || matches!(name[i - 1], '/' | '-' | '_' | '.' | ' ') {
+            score += 3;
+        }
+        next = i + 1;
+    }
+
+    Some(score * 100 - name.len() as i64)
+}
+
+/// Restores the terminal when dropped, however the picker exits.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        if let Err(e) = execute!(io::stderr(), terminal::EnterAlternateScreen) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e);
+        }
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), terminal::LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The state of the picker.
+struct Picker<'a, F> {
+    items: &'a [ItemName],
+    preview: F,
+    previews: HashMap<usize, Vec<String>>,
+    query: String,
+    /// Indices into `items` of the items matching the query, best first.
+    matches: Vec<usize>,
+    selected: usize,
+    offset: usize,
+}
+
+impl<F: FnMut(&ItemName) -> Vec<String>> Picker<'_, F> {
+    fn filter(&mut self) {
+        let mut matches: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| score(&self.query, item.as_str()).map(|score| (score, i)))
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        self.matches = matches.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn selection(&self) -> Option<&ItemName> {
+        self.matches.get(self.selected).map(|&i| &self.items[i])
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let fit = |line: &str| line.chars().take(width).collect::<String>();
+
+        let preview = if height > PREVIEW_LINES + 4 {
+            PREVIEW_LINES
+        } else {
+            0
+        };
+        let rows = height.saturating_sub(2 + preview) as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+
+        queue!(
+            out,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 1)
+        )?;
+        let count = format!("  {}/{}", self.matches.len(), self.items.len());
+        queue!(out, PrintStyledContent(fit(&count).dim()))?;
+
+        for (row, &i) in self.matches.iter().skip(self.offset).take(rows).enumerate() {
+            let line = fit(&format!("  {}", self.items[i]));
+            queue!(out, cursor::MoveTo(0, row as u16 + 2))?;
+            if self.offset + row == self.selected {
+                queue!(out, PrintStyledContent(line.reverse()))?;
+            } else {
+                write!(out, "{}", line)?;
+            }
+        }
+
+        if preview > 0 {
+            let top = height - preview;
+            queue!(out, cursor::MoveTo(0, top))?;
+            queue!(out, PrintStyledContent("─".repeat(width).dim()))?;
+            if let Some(&i) = self.matches.get(self.selected) {
+                let (items, render) = (self.items, &mut self.preview);
+                let lines = self.previews.entry(i).or_insert_with(|| render(&items[i]));
+                for (row, line) in lines.iter().take(preview as usize - 1).enumerate() {
+                    queue!(out, cursor::MoveTo(0, top + 1 + row as u16))?;
+                    write!(out, "{}", fit(&format!("  {}", line)))?;
+                }
+            }
+        }
+
+        let prompt = format!("> {}", self.query);
+        queue!(out, cursor::MoveTo(0, 0))?;
+        write!(out, "{}", fit(&prompt))?;
+        queue!(
+            out,
+            cursor::MoveTo(prompt.chars().count().min(width) as u16, 0),
+            cursor::Show
+        )?;
+        out.flush()
+    }
+}
+
+/// Let the user choose one of `items`, filtering them as they type and showing the lines returned
+/// by `preview` for the selected item. Returns `None` if the user cancels.
+///
+/// With `new`, the name typed is returned instead of the selected item, which Tab copies into the
+/// query, so that the user can name a new item.
+pub fn pick<F>(items: &[ItemName], new: bool, preview: F) -> Result<Option<String>, Error>
+where
+    F: FnMut(&ItemName) -> Vec<String>,
+{
+    let mut picker = Picker {
+        items,
+        preview,
+        previews: HashMap::new(),
+        query: String::new(),
+        matches: vec![],
+        selected: 0,
+        offset: 0,
+    };
+    picker.filter();
+
+    let _screen = Screen::enter()?;
+    let mut out = io::stderr();
+
+    loop {
+        picker.draw(&mut out)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match (key.code, ctrl) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), true) | (KeyCode::Char('g'), true) => {
+                return Ok(None)
+            }
+            (KeyCode::Enter, _) => {
+                return Ok(if new {
+                    Some(picker.query).filter(|query| !query.is_empty())
+                } else {
+                    picker.selection().map(|item| item.to_string())
+                });
+            }
+            (KeyCode::Tab, _) if new => {
+                if let Some(item) = picker.selection() {
+                    picker.query = item.to_string();
+                    picker.filter();
+                }
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('p'), true) => {
+                picker.selected = picker.selected.saturating_sub(1);
+            }
+            (KeyCode::Down, _) | (KeyCode::Char('n'), true)
+                if picker.selected + 1 < picker.matches.len() =>
+            {
+                picker.selected += 1;
+            }
+            (KeyCode::Char('u'), true) => {
+                picker.query.clear();
+                picker.filter();
+            }
+            (KeyCode::Backspace, _) => {
+                picker.query.pop();
+                picker.filter();
+            }
+            (KeyCode::Char(c), false) => {
+                picker.query.push(c);
+                picker.filter();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoring_matches() {
+        assert_eq!(score("xyz", "github.com"), None);
+        assert_eq!(score("", "github.com"), Some(-10));
+
+        // Consecutive matches and matches starting a word rank first
+        assert!(score("git", "work/github") > score("git", "email/gmail-test"));
+        assert!(score("GH", "git-hub") > score("gh", "aaaaaaagaaah"));
+        // Then shorter names
+        assert!(score("db", "db") > score("db", "prod/db"));
+    }
+}
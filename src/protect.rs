@@ -0,0 +1,60 @@
+//! Keeping secrets out of places they could leak from.
+//!
+//! Decrypted items and secret keys are held in buffers that are zeroed when dropped (see
+//! [`secrecy::SecretString`] and [`zeroize::Zeroizing`]), but they could still be written to disk
+//! in a core dump if passage crashes, or to swap while it runs.
+
+use std::io;
+
+/// Prevent the current process from dumping core, and on Linux from being attached to by
+/// debuggers running as the same user.
+#[cfg(unix)]
+pub fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: setrlimit only reads the given struct
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: PR_SET_DUMPABLE takes a single integer argument and has no other effects
+    #[cfg(target_os = "linux")]
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Does nothing on this platform.
+#[cfg(not(unix))]
+pub fn disable_core_dumps() -> io::Result<()> {
+    Ok(())
+}
+
+/// Lock all current and future memory of the process into RAM so that secrets are never written
+/// to swap.
+///
+/// This fails if the process may not lock that much memory (see `ulimit -l`). Once locked, any
+/// allocation beyond the limit fails, so this is only done when asked for.
+#[cfg(unix)]
+pub fn lock_memory() -> io::Result<()> {
+    // SAFETY: mlockall only changes how the process' memory is paged
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Always fails on this platform.
+#[cfg(not(unix))]
+pub fn lock_memory() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "locking memory is not supported on this platform",
+    ))
+}
@@ -1,132 +1,1073 @@
+//! The password store itself.
+
 use age::x25519::{Identity, Recipient};
-use std::fs::{self, DirEntry, File, OpenOptions};
-use std::io::prelude::*;
-use std::io::{self, BufReader};
+use rand::Rng;
+use rayon::prelude::*;
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use zeroize::Zeroizing;
+
+use crate::backend::{BackendKind, FileBackend, Replacement, StoreBackend};
+use crate::manifest::{self, Entry, Hashing, Manifest, ManifestKey};
+use crate::{crypt, entry, error::Error, item::ItemName, perms};
+
+/// Maps the name of each item of a store that hides its item names to the random ID under which
+/// the item is kept.
+type Index = BTreeMap<ItemName, ItemName>;
+
+/// A random ID under which an item of a store that hides its item names is kept.
+fn random_id() -> ItemName {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    ItemName::new(&id).unwrap()
+}
 
-use crate::{crypt, error::Error, key};
+/// How far [`PasswordStore::reencrypt_with_progress`] has got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of items in the store.
+    pub total: usize,
+    /// The number of items re-encrypted so far.
+    pub reencrypted: usize,
+    /// The number of items that could not be re-encrypted so far.
+    pub failed: usize,
+}
 
+/// A collection of age-encrypted items.
+///
+/// Every item is encrypted to each of the store's recipients, which are kept alongside the items
+/// (in the `.public-keys` file of a store on disk). Where the encrypted items are kept is up to
+/// the store's [`StoreBackend`].
+///
+/// A store can also hide the names of its items (see [`PasswordStore::hide_names`]). Its items are
+/// then kept under random IDs, and an index mapping names to IDs is kept encrypted to the store's
+/// recipients.
+///
+/// A store can keep a manifest recording the contents of the store, to detect tampering by anyone
+/// else who can write to it (see [`PasswordStore::sign_manifest`]).
+///
+/// Stores that hide their item names or keep a manifest must be unlocked with a recipient's secret
+/// key using [`PasswordStore::unlock`] before items can be listed or modified.
 pub struct PasswordStore {
-    pub dir: PathBuf,
+    backend: Box<dyn StoreBackend>,
+    /// The public keys every item is encrypted to.
     pub recipients: Vec<Recipient>,
+    hides_names: bool,
+    has_manifest: bool,
+    index: RwLock<Option<Index>>,
+    manifest_key: RwLock<Option<ManifestKey>>,
 }
 
 impl PasswordStore {
+    /// Load the store in `dir` using the default file backend. The store need not exist yet.
     pub fn new(dir: PathBuf) -> PasswordStore {
+        PasswordStore::with_backend(Box::new(FileBackend::new(dir)))
+    }
+
+    /// Load a store kept in the given backend.
+    pub fn with_backend(backend: Box<dyn StoreBackend>) -> PasswordStore {
         let mut recipients: Vec<Recipient> = vec![];
 
-        if let Ok(file) = File::open(dir.join(".public-keys")) {
-            let buf = BufReader::new(file);
-            buf.lines()
-                .filter_map(|result| result.ok())
+        if let Ok(Some(public_keys)) = backend.read_meta("public-keys") {
+            String::from_utf8_lossy(&public_keys)
+                .lines()
                 .filter(|line| !line.starts_with('#'))
                 .map(|line| line.parse())
                 .filter_map(|result| result.ok())
                 .for_each(|recipient| recipients.push(recipient));
         }
 
-        PasswordStore { dir, recipients }
+        let hides_names = matches!(backend.read_meta("index"), Ok(Some(_)));
+        let has_manifest = matches!(backend.read_meta("manifest"), Ok(Some(_)));
+
+        PasswordStore {
+            backend,
+            recipients,
+            hides_names,
+            has_manifest,
+            index: RwLock::new(None),
+            manifest_key: RwLock::new(None),
+        }
     }
 
-    pub fn exists(&self, name: &str) -> bool {
-        self.dir
-            .join(PathBuf::from(name.to_string() + ".age"))
-            .exists()
+    /// Load an existing store with whichever backend it uses, failing with
+    /// [`Error::StoreNotInitialized`] if there is none.
+    pub fn open(dir: PathBuf) -> Result<PasswordStore, Error> {
+        let store = PasswordStore::with_backend(BackendKind::detect(&dir).open(dir)?);
+        if !store.is_initialized()? {
+            return Err(Error::StoreNotInitialized);
+        }
+
+        Ok(store)
     }
 
-    pub fn items(&self) -> io::Result<Vec<DirEntry>> {
-        fn scan(dir: &Path, entries: &mut Vec<DirEntry>) -> io::Result<()> {
-            for entry in fs::read_dir(&dir)?
-                .filter_map(|e| e.ok())
-                .collect::<Vec<DirEntry>>()
-                .into_iter()
-            {
-                let path = entry.path();
-                if path.is_dir() {
-                    scan(&path, entries)?;
-                } else if let Some(ext) = path.extension() {
-                    if ext == "age" {
-                        entries.push(entry);
-                    }
+    /// Create a new store in `dir` whose items are encrypted to `recipients`.
+    pub fn init(dir: PathBuf, recipients: Vec<Recipient>) -> Result<PasswordStore, Error> {
+        let mut store = PasswordStore::new(dir);
+        store.recipients.clear();
+        store.add_recipients(recipients);
+        store.save_recipients()?;
+        Ok(store)
+    }
+
+    /// The backend holding the store's items.
+    pub fn backend(&self) -> &dyn StoreBackend {
+        self.backend.as_ref()
+    }
+
+    /// Whether the store has been initialized, i.e. its recipients have been saved.
+    pub fn is_initialized(&self) -> Result<bool, Error> {
+        Ok(self.backend.read_meta("public-keys")?.is_some())
+    }
+
+    /// Add recipients to the store, ignoring any that are already present.
+    ///
+    /// Existing items are not affected until the store is re-encrypted and the change is not
+    /// persisted until [`PasswordStore::save_recipients`] is called.
+    pub fn add_recipients<I: IntoIterator<Item = Recipient>>(&mut self, recipients: I) {
+        self.recipients.extend(recipients);
+        self.recipients.sort_unstable_by_key(|r| r.to_string());
+        self.recipients.dedup_by_key(|r| r.to_string());
+    }
+
+    /// Remove a recipient from the store.
+    ///
+    /// Like [`PasswordStore::add_recipients`], this only affects items written afterwards.
+    pub fn remove_recipient(&mut self, recipient: &Recipient) {
+        let recipient = recipient.to_string();
+        self.recipients.retain(|r| r.to_string() != recipient);
+    }
+
+    /// Persist the store's recipients, creating the store if needed.
+    pub fn save_recipients(&self) -> Result<(), Error> {
+        self.manifest()?;
+
+        let public_keys: String = self.recipients.iter().map(|r| format!("{}\n", r)).collect();
+        self.backend
+            .write_meta("public-keys", public_keys.as_bytes())?;
+        self.record(vec![(
+            Entry::Meta("public-keys".into()),
+            Some(manifest::hash(public_keys.as_bytes())),
+        )])
+    }
+
+    /// Whether the store hides the names of its items.
+    pub fn hides_names(&self) -> bool {
+        self.hides_names
+    }
+
+    /// Whether the store must be unlocked with [`PasswordStore::unlock`] before its items can be
+    /// listed or modified, because it hides its item names or keeps a manifest.
+    pub fn needs_unlock(&self) -> bool {
+        (self.hides_names && self.index.read().unwrap().is_none())
+            || (self.has_manifest && self.manifest_key.read().unwrap().is_none())
+    }
+
+    /// Unlock the store with a recipient's secret key, decrypting the index of a store that hides
+    /// its item names and the key that authenticates the manifest. Does nothing if the store is
+    /// already unlocked.
+    pub fn unlock(&self, key: &Identity) -> Result<(), Error> {
+        if !self.needs_unlock() {
+            return Ok(());
+        }
+
+        let manifest_key = match self.has_manifest {
+            true => Some(self.read_manifest_key(key)?),
+            false => None,
+        };
+        let mut index = None;
+        if self.hides_names {
+            let encrypted = self
+                .backend
+                .read_meta("index")?
+                .ok_or(Error::SecretKeyRequired)?;
+            if let Some(manifest_key) = &manifest_key {
+                let manifest = self.read_manifest(manifest_key)?;
+                if manifest.entries.get(&Entry::Meta("index".into()))
+                    != Some(&manifest::hash(&encrypted))
+                {
+                    return Err(Error::Tampered("The index".into()));
                 }
             }
 
-            Ok(())
+            let mut entries = Index::new();
+            let decrypted = crypt::decrypt_with_key(&encrypted, key)?;
+            for line in String::from_utf8_lossy(&decrypted).lines() {
+                if let Some((id, name)) = line.split_once(' ') {
+                    entries.insert(name.parse()?, id.parse()?);
+                }
+            }
+            index = Some(entries);
+        }
+
+        *self.manifest_key.write().unwrap() = manifest_key;
+
+        if let Some(index) = index {
+            // Finish hiding items whose move was interrupted
+            for (name, id) in &index {
+                if !self.backend.exists(id)? && self.backend.exists(name)? {
+                    self.move_item(name, id)?;
+                }
+            }
+
+            *self.index.write().unwrap() = Some(index);
+        }
+
+        Ok(())
+    }
+
+    /// Start hiding the names of the store's items, moving every existing item to a random ID.
+    ///
+    /// The change cannot be undone.
+    pub fn hide_names(&mut self, key: &Identity) -> Result<(), Error> {
+        self.unlock(key)?;
+        if self.hides_names {
+            return Ok(());
+        }
+
+        let index: Index = self
+            .backend
+            .list()?
+            .into_iter()
+            .map(|name| (name, random_id()))
+            .collect();
+
+        // The index is saved first so that an interrupted move can be finished by unlock
+        self.save_index(&index)?;
+        self.hides_names = true;
+        for (name, id) in &index {
+            self.move_item(name, id)?;
+        }
+
+        *self.index.write().unwrap() = Some(index);
+        Ok(())
+    }
+
+    /// Encrypt the index to the store's recipients and persist it.
+    fn save_index(&self, index: &Index) -> Result<(), Error> {
+        let contents: String = index
+            .iter()
+            .map(|(name, id)| format!("{} {}\n", id, name))
+            .collect();
+        let encrypted = crypt::encrypt_with_keys(contents.as_bytes(), &self.recipients)?;
+        self.backend.write_meta("index", &encrypted)?;
+        self.record(vec![(
+            Entry::Meta("index".into()),
+            Some(manifest::hash(&encrypted)),
+        )])
+    }
+
+    /// Rename an item in the backend.
+    fn move_item(&self, from: &ItemName, to: &ItemName) -> Result<(), Error> {
+        self.backend.rename(from, to)?;
+        let data = self.backend.read(to)?;
+        self.record(vec![
+            (Entry::Item(from.clone()), None),
+            (Entry::Item(to.clone()), Some(manifest::hash(&data))),
+        ])
+    }
+
+    /// Encrypt the manifest key to the store's recipients and persist it.
+    fn save_manifest_key(&self, key: &ManifestKey) -> Result<(), Error> {
+        let encrypted = crypt::encrypt_with_keys(key.as_bytes(), &self.recipients)?;
+        self.backend.write_meta("manifest-key", &encrypted)?;
+        self.record(vec![(
+            Entry::Meta("manifest-key".into()),
+            Some(manifest::hash(&encrypted)),
+        )])
+    }
+
+    fn read_manifest_key(&self, key: &Identity) -> Result<ManifestKey, Error> {
+        let encrypted = self
+            .backend
+            .read_meta("manifest-key")?
+            .ok_or(Error::InvalidManifest)?;
+        ManifestKey::from_bytes(&crypt::decrypt_with_key(&encrypted, key)?)
+    }
+
+    fn read_manifest(&self, key: &ManifestKey) -> Result<Manifest, Error> {
+        let data = self
+            .backend
+            .read_meta("manifest")?
+            .ok_or(Error::InvalidManifest)?;
+        Manifest::verify(&data, key)
+    }
+
+    /// The store's manifest, after checking that it was signed with the store's manifest key, or
+    /// `None` if the store does not keep one.
+    pub fn manifest(&self) -> Result<Option<Manifest>, Error> {
+        if !self.has_manifest {
+            return Ok(None);
+        }
+
+        match &*self.manifest_key.read().unwrap() {
+            Some(key) => self.read_manifest(key).map(Some),
+            None => Err(Error::SecretKeyRequired),
+        }
+    }
+
+    /// Update the manifest after items or metadata were written with the given hashes (or, with
+    /// `None`, removed).
+    fn record(&self, changes: Vec<(Entry, Option<String>)>) -> Result<(), Error> {
+        let mut manifest = match self.manifest()? {
+            Some(manifest) => manifest,
+            None => return Ok(()),
+        };
+
+        for (entry, hash) in changes {
+            match hash {
+                Some(hash) => manifest.entries.insert(entry, hash),
+                None => manifest.entries.remove(&entry),
+            };
+        }
+        manifest.version += 1;
+
+        let guard = self.manifest_key.read().unwrap();
+        let key = guard.as_ref().ok_or(Error::SecretKeyRequired)?;
+        self.backend.write_meta("manifest", &manifest.sign(key))
+    }
+
+    /// Make sure that the store may be modified: if it keeps a manifest, the store must be
+    /// unlocked and neither the manifest nor the recipients may have been tampered with.
+    fn check_writable(&self) -> Result<(), Error> {
+        if let Some(manifest) = self.manifest()? {
+            let public_keys = self.backend.read_meta("public-keys")?.unwrap_or_default();
+            if manifest.entries.get(&Entry::Meta("public-keys".into()))
+                != Some(&manifest::hash(&public_keys))
+            {
+                return Err(Error::Tampered("The list of recipients".into()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The hash of every item and metadata blob currently in the store.
+    fn current_entries(&self) -> Result<BTreeMap<Entry, String>, Error> {
+        let mut entries = BTreeMap::new();
+        for name in self.backend.list()? {
+            let data = self.backend.read(&name)?;
+            entries.insert(Entry::Item(name), manifest::hash(&data));
+        }
+        for key in &["public-keys", "index", "manifest-key"] {
+            if let Some(data) = self.backend.read_meta(key)? {
+                entries.insert(Entry::Meta(key.to_string()), manifest::hash(&data));
+            }
         }
 
-        let mut entries: Vec<DirEntry> = vec![];
-        scan(&self.dir, &mut entries)?;
         Ok(entries)
     }
 
-    pub fn insert(&self, name: &str, secret: &str) -> Result<(), Error> {
-        let path = self.dir.join(PathBuf::from(name.to_string() + ".age"));
-        fs::create_dir_all(&path.parent().unwrap())?;
+    /// Start keeping a manifest, or bring the manifest up to date with the current contents of the
+    /// store, accepting any changes made by others since it was last updated. A new store is given
+    /// a random manifest key, encrypted to the store's recipients.
+    pub fn sign_manifest(&mut self, key: &Identity) -> Result<(), Error> {
+        self.unlock(key)?;
 
-        let mut file = match OpenOptions::new().create_new(true).write(true).open(&path) {
-            Ok(f) => f,
-            Err(e) => match e.kind() {
-                io::ErrorKind::AlreadyExists => return Err(Error::ItemAlreadyExists(name.into())),
-                _ => return Err(e.into()),
-            },
+        let existing = self.manifest_key.read().unwrap().clone();
+        let (manifest_key, previous) = match existing {
+            Some(manifest_key) => {
+                let previous = self.read_manifest(&manifest_key).ok();
+                (manifest_key, previous)
+            }
+            None => {
+                let manifest_key = ManifestKey::generate();
+                self.save_manifest_key(&manifest_key)?;
+                (manifest_key, None)
+            }
         };
+        let mut manifest = previous.unwrap_or_default();
+        manifest.entries = self.current_entries()?;
+        manifest.version += 1;
 
-        let encrypted = crypt::encrypt_with_keys(&secret, &self.recipients)?;
-        file.write_all(&encrypted)?;
+        self.backend
+            .write_meta("manifest", &manifest.sign(&manifest_key))?;
+        self.has_manifest = true;
+        *self.manifest_key.write().unwrap() = Some(manifest_key);
 
         Ok(())
     }
 
-    pub fn get(&self, name: &str) -> Result<String, Error> {
-        let path = self.dir.join(PathBuf::from(name.to_string() + ".age"));
-        if !path.exists() {
-            return Err(Error::ItemNotFound(name.into()));
+    /// Compare the store to its manifest, describing every difference found. Fails with
+    /// [`Error::InvalidManifest`] if the manifest itself was tampered with.
+    pub fn verify(&self) -> Result<Vec<String>, Error> {
+        let manifest = match self.manifest()? {
+            Some(manifest) => manifest,
+            None => return Ok(vec![]),
+        };
+
+        let names: BTreeMap<ItemName, ItemName> = match &*self.index.read().unwrap() {
+            Some(index) => index
+                .iter()
+                .map(|(name, id)| (id.clone(), name.clone()))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let describe = |entry: &Entry| match entry {
+            Entry::Item(id) if self.hides_names => match names.get(id) {
+                Some(name) => name.to_string(),
+                None => format!("An unknown item ({})", id),
+            },
+            Entry::Item(name) => name.to_string(),
+            Entry::Meta(key) if key == "public-keys" => "The list of recipients".to_string(),
+            Entry::Meta(key) => format!("The {}", key),
+        };
+
+        let current = self.current_entries()?;
+        let mut problems = vec![];
+        for (entry, hash) in &manifest.entries {
+            match current.get(entry) {
+                None => problems.push(format!("{} was removed", describe(entry))),
+                Some(current) if current != hash => {
+                    problems.push(format!("{} was modified", describe(entry)))
+                }
+                _ => {}
+            }
+        }
+        for entry in current.keys() {
+            if !manifest.entries.contains_key(entry) {
+                problems.push(format!("{} was added", describe(entry)));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// The name under which the backend keeps an item, or `None` if the item is not in the index.
+    fn locate(&self, name: &ItemName) -> Result<Option<ItemName>, Error> {
+        if !self.hides_names {
+            return Ok(Some(name.clone()));
+        }
+
+        match &*self.index.read().unwrap() {
+            Some(index) => Ok(index.get(name).cloned()),
+            None => Err(Error::SecretKeyRequired),
+        }
+    }
+
+    /// Like [`PasswordStore::locate`], but fails if the item does not exist.
+    fn locate_existing(&self, name: &ItemName) -> Result<ItemName, Error> {
+        self.locate(name)?
+            .ok_or_else(|| Error::ItemNotFound(name.to_string()))
+    }
+
+    /// Whether the store contains the given item.
+    pub fn exists(&self, name: &ItemName) -> bool {
+        match self.locate(name) {
+            Ok(Some(id)) => self.backend.exists(&id).unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Describe every way in which the store or the secret keys at `key_paths` could be modified
+    /// by another user.
+    pub fn check_permissions<P: AsRef<Path>>(&self, key_paths: &[P]) -> Vec<String> {
+        let mut problems = self.backend.check_permissions();
+        problems.extend(key_paths.iter().filter_map(perms::check));
+        problems
+    }
+
+    /// Encrypt `secret` and add it to the store as a new item.
+    ///
+    /// Fails with [`Error::ItemAlreadyExists`] if the item already exists.
+    pub fn insert(&self, name: &ItemName, secret: &str) -> Result<(), Error> {
+        self.insert_bytes(name, secret.as_bytes())
+    }
+
+    /// Like [`PasswordStore::insert`], but for secrets that need not be text, such as key files.
+    pub fn insert_bytes(&self, name: &ItemName, secret: &[u8]) -> Result<(), Error> {
+        self.insert_from(name, secret)
+    }
+
+    /// Like [`PasswordStore::insert_bytes`], but encrypting the secret as it is read from `secret`
+    /// so that large files need not fit in memory.
+    pub fn insert_from<R: Read>(&self, name: &ItemName, secret: R) -> Result<(), Error> {
+        self.check_writable()?;
+
+        if !self.hides_names {
+            if self.backend.exists(name)? {
+                return Err(Error::ItemAlreadyExists(name.to_string()));
+            }
+            let hash = self.write_item(name, secret, false)?;
+            return self.record(vec![(Entry::Item(name.clone()), Some(hash))]);
+        }
+
+        let mut guard = self.index.write().unwrap();
+        let index = guard.as_mut().ok_or(Error::SecretKeyRequired)?;
+        if index.contains_key(name) {
+            return Err(Error::ItemAlreadyExists(name.to_string()));
+        }
+
+        let id = random_id();
+        let hash = self.write_item(&id, secret, false)?;
+        self.record(vec![(Entry::Item(id.clone()), Some(hash))])?;
+        index.insert(name.clone(), id);
+        self.save_index(index)
+    }
+
+    /// Encrypt `secret` into the item the backend keeps under `id`, returning the hash of the
+    /// ciphertext.
+    fn write_item<R: Read>(
+        &self,
+        id: &ItemName,
+        secret: R,
+        replace: bool,
+    ) -> Result<String, Error> {
+        let mut writer = Hashing::new(self.backend.writer(id, replace)?);
+        crypt::encrypt_stream(secret, &mut writer, &self.recipients)?;
+        let (writer, hash) = writer.finish();
+        writer.commit()?;
+
+        Ok(hash)
+    }
+
+    /// Decrypt an item with the given secret key.
+    ///
+    /// Fails with [`Error::NotText`] if the item is not valid UTF-8, in which case it can be
+    /// retrieved with [`PasswordStore::get_bytes`].
+    pub fn get(&self, name: &ItemName, key: &Identity) -> Result<SecretString, Error> {
+        match std::str::from_utf8(&self.get_bytes(name, key)?) {
+            Ok(secret) => Ok(secret.into()),
+            Err(_) => Err(Error::NotText(name.to_string())),
         }
+    }
 
-        let key = key::read_secret_key(key::secret_key_path())?;
-        let decrypted = crypt::decrypt_with_key(&fs::read(path)?, &key)?;
+    /// Decrypt an item with the given secret key, whether or not it is text.
+    pub fn get_bytes(&self, name: &ItemName, key: &Identity) -> Result<Zeroizing<Vec<u8>>, Error> {
+        self.unlock(key)?;
+        let id = self.locate_existing(name)?;
+        let encrypted = self.backend.read(&id)?;
+
+        let mut decrypted = crypt::plaintext_buffer(encrypted.len());
+        self.decrypt_item(name, id, &encrypted[..], key, &mut *decrypted)?;
 
         Ok(decrypted)
     }
 
-    pub fn update(&self, name: &str, secret: &str) -> Result<(), Error> {
-        let path = self.dir.join(PathBuf::from(name.to_string() + ".age"));
-        if !path.exists() {
-            return Err(Error::ItemNotFound(name.into()));
+    /// Decrypt an item with the given secret key, writing it to `output` as it is decrypted so that
+    /// large items need not fit in memory.
+    ///
+    /// If an error is returned, part of the item may already have been written and must be
+    /// discarded: in particular, a store that keeps a manifest only detects that the item was
+    /// tampered with once all of it has been read.
+    pub fn get_to<W: Write>(
+        &self,
+        name: &ItemName,
+        key: &Identity,
+        output: W,
+    ) -> Result<(), Error> {
+        self.unlock(key)?;
+        let id = self.locate_existing(name)?;
+        let encrypted = self.backend.reader(&id)?;
+        self.decrypt_item(name, id, encrypted, key, output)
+    }
+
+    /// Decrypt the item `name`, kept by the backend under `id`, from `encrypted` into `output`,
+    /// checking it against the manifest.
+    fn decrypt_item<R: Read, W: Write>(
+        &self,
+        name: &ItemName,
+        id: ItemName,
+        encrypted: R,
+        key: &Identity,
+        output: W,
+    ) -> Result<(), Error> {
+        let manifest = self.manifest()?;
+
+        let mut reader = Hashing::new(encrypted);
+        let decrypted = crypt::decrypt_stream(&mut reader, output, key);
+
+        if let Some(manifest) = manifest {
+            // Hash whatever was not read by the decryption too, so that a modified item is
+            // reported as such even if it could not be decrypted
+            io::copy(&mut reader, &mut io::sink())?;
+            if manifest.entries.get(&Entry::Item(id)) != Some(&reader.finish().1) {
+                return Err(Error::Tampered(name.to_string()));
+            }
         }
 
-        let encrypted = crypt::encrypt_with_keys(&secret, &self.recipients)?;
-        File::create(path)?.write_all(&encrypted)?;
+        decrypted
+    }
 
-        Ok(())
+    /// Decrypt an item with the given secret key and split it into its fields.
+    pub fn get_entry(&self, name: &ItemName, key: &Identity) -> Result<entry::Entry, Error> {
+        Ok(entry::Entry::parse(self.get(name, key)?.expose_secret()))
+    }
+
+    /// Add an entry to the store as a new item, like [`PasswordStore::insert`].
+    pub fn insert_entry(&self, name: &ItemName, entry: &entry::Entry) -> Result<(), Error> {
+        self.insert(name, &Zeroizing::new(entry.to_string()))
+    }
+
+    /// Replace the secret of an existing item.
+    pub fn update(&self, name: &ItemName, secret: &str) -> Result<(), Error> {
+        self.update_bytes(name, secret.as_bytes())
+    }
+
+    /// Like [`PasswordStore::update`], but for secrets that need not be text.
+    pub fn update_bytes(&self, name: &ItemName, secret: &[u8]) -> Result<(), Error> {
+        self.update_from(name, secret)
+    }
+
+    /// Like [`PasswordStore::update_bytes`], but encrypting the secret as it is read from `secret`.
+    pub fn update_from<R: Read>(&self, name: &ItemName, secret: R) -> Result<(), Error> {
+        self.check_writable()?;
+
+        let id = self.locate_existing(name)?;
+        if !self.backend.exists(&id)? {
+            return Err(Error::ItemNotFound(name.to_string()));
+        }
+
+        let hash = self.write_item(&id, secret, true)?;
+        self.record(vec![(Entry::Item(id), Some(hash))])
+    }
+
+    /// Remove an item from the store.
+    pub fn delete(&self, name: &ItemName) -> Result<(), Error> {
+        self.check_writable()?;
+
+        if !self.hides_names {
+            self.backend.delete(name)?;
+            return self.record(vec![(Entry::Item(name.clone()), None)]);
+        }
+
+        let mut guard = self.index.write().unwrap();
+        let index = guard.as_mut().ok_or(Error::SecretKeyRequired)?;
+        let id = index
+            .remove(name)
+            .ok_or_else(|| Error::ItemNotFound(name.to_string()))?;
+
+        // Forget the item before deleting it, so that a failure leaves an unreachable item rather
+        // than an index entry pointing nowhere
+        self.save_index(index)?;
+        self.backend.delete(&id)?;
+        self.record(vec![(Entry::Item(id), None)])
+    }
+
+    /// Rename an item, failing with [`Error::ItemAlreadyExists`] if `to` already exists.
+    pub fn rename(&self, from: &ItemName, to: &ItemName) -> Result<(), Error> {
+        self.check_writable()?;
+
+        if !self.hides_names {
+            return self.move_item(from, to);
+        }
+
+        let mut guard = self.index.write().unwrap();
+        let index = guard.as_mut().ok_or(Error::SecretKeyRequired)?;
+        if index.contains_key(to) {
+            return Err(Error::ItemAlreadyExists(to.to_string()));
+        }
+        let id = index
+            .remove(from)
+            .ok_or_else(|| Error::ItemNotFound(from.to_string()))?;
+        index.insert(to.clone(), id);
+        self.save_index(index)
+    }
+
+    /// Re-encrypt every item in the store to the store's current recipients, using every core.
+    ///
+    /// Items that cannot be re-encrypted, for instance because they are not encrypted to `key`, are
+    /// left as they were and returned along with the reason. Every other item is re-encrypted or,
+    /// if an error is returned, the store is left as it was.
+    pub fn reencrypt(&self, key: &Identity) -> Result<Vec<(ItemName, Error)>, Error> {
+        self.reencrypt_with_progress(key, |_| {})
+    }
+
+    /// Like [`PasswordStore::reencrypt`], calling `progress` before starting and after each item.
+    /// It is called from several threads at once.
+    pub fn reencrypt_with_progress<F>(
+        &self,
+        key: &Identity,
+        progress: F,
+    ) -> Result<Vec<(ItemName, Error)>, Error>
+    where
+        F: Fn(Progress) + Sync,
+    {
+        self.unlock(key)?;
+        self.check_writable()?;
+        let manifest = self.manifest()?;
+
+        let ids = self.backend.list()?;
+        let total = ids.len();
+        let reencrypted = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        progress(Progress {
+            total,
+            ..Progress::default()
+        });
+
+        let replacement = self.backend.replacement()?;
+        let results: Vec<(ItemName, Result<String, Error>)> = ids
+            .into_par_iter()
+            .map(|id| {
+                let result = self.reencrypt_item(&id, key, manifest.as_ref(), &*replacement);
+                match result {
+                    Ok(_) => reencrypted.fetch_add(1, Ordering::Relaxed),
+                    Err(_) => failed.fetch_add(1, Ordering::Relaxed),
+                };
+                progress(Progress {
+                    total,
+                    reencrypted: reencrypted.load(Ordering::Relaxed),
+                    failed: failed.load(Ordering::Relaxed),
+                });
+                (id, result)
+            })
+            .collect();
+
+        let mut hashes = vec![];
+        let mut failures = vec![];
+        for (id, result) in results {
+            match result {
+                Ok(hash) => hashes.push((Entry::Item(id), Some(hash))),
+                Err(e) => failures.push((self.name_of(&id), e)),
+            }
+        }
+        failures.sort_by(|a, b| a.0.cmp(&b.0));
+
+        replacement.commit()?;
+        self.record(hashes)?;
+
+        if let Some(index) = &*self.index.read().unwrap() {
+            self.save_index(index)?;
+        }
+        let manifest_key = self.manifest_key.read().unwrap().clone();
+        if let Some(manifest_key) = manifest_key {
+            self.save_manifest_key(&manifest_key)?;
+        }
+
+        Ok(failures)
     }
 
-    pub fn delete(&self, name: &str) -> Result<(), Error> {
-        if let Err(e) = fs::remove_file(self.dir.join(name.to_string() + ".age")) {
-            match e.kind() {
-                io::ErrorKind::NotFound => return Err(Error::ItemNotFound(name.into())),
-                _ => return Err(e.into()),
+    /// Decrypt the item the backend keeps under `id` and stage it encrypted to the current
+    /// recipients in `replacement`, returning the hash of the new ciphertext.
+    fn reencrypt_item(
+        &self,
+        id: &ItemName,
+        key: &Identity,
+        manifest: Option<&Manifest>,
+        replacement: &dyn Replacement,
+    ) -> Result<String, Error> {
+        let encrypted = self.backend.read(id)?;
+        if let Some(manifest) = manifest {
+            if manifest.entries.get(&Entry::Item(id.clone())) != Some(&manifest::hash(&encrypted)) {
+                return Err(Error::Tampered(self.name_of(id).to_string()));
             }
         }
 
-        Ok(())
+        let secret = crypt::decrypt_with_key(&encrypted, key)?;
+        let mut writer = Hashing::new(replacement.writer(id)?);
+        crypt::encrypt_stream(&secret[..], &mut writer, &self.recipients)?;
+        let (writer, hash) = writer.finish();
+        writer.commit()?;
+
+        Ok(hash)
     }
 
-    pub fn reencrypt(&self, key: &Identity) -> Result<(), Error> {
-        for item in self.items()? {
-            let mut cypher = vec![];
-            File::open(item.path())?.read_to_end(&mut cypher)?;
+    /// The name of the item the backend keeps under `id`.
+    fn name_of(&self, id: &ItemName) -> ItemName {
+        match &*self.index.read().unwrap() {
+            Some(index) => index
+                .iter()
+                .find(|(_, i)| *i == id)
+                .map(|(name, _)| name.clone())
+                .unwrap_or_else(|| id.clone()),
+            None => id.clone(),
+        }
+    }
 
-            let secret = crypt::decrypt_with_key(&cypher, key)?;
-            let encrypted = crypt::encrypt_with_keys(&secret, &self.recipients)?;
-            File::create(item.path())?.write_all(&encrypted)?;
+    /// The names of all items in the store, in sorted order.
+    pub fn list(&self) -> Result<Vec<ItemName>, Error> {
+        if self.hides_names {
+            return match &*self.index.read().unwrap() {
+                Some(index) => Ok(index.keys().cloned().collect()),
+                None => Err(Error::SecretKeyRequired),
+            };
         }
 
+        let mut list = self.backend.list()?;
+        list.sort();
+        Ok(list)
+    }
+
+    /// The names of all items containing `pattern`, ignoring case, in sorted order.
+    pub fn find(&self, pattern: &str) -> Result<Vec<ItemName>, Error> {
+        let pattern = pattern.to_lowercase();
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|name| name.as_str().to_lowercase().contains(&pattern))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn memory_store(key: &Identity) -> PasswordStore {
+        let mut store = PasswordStore::with_backend(Box::new(MemoryBackend::new()));
+        store.add_recipients(Some(key.to_public()));
+        store.save_recipients().unwrap();
+        store
+    }
+
+    #[test]
+    fn item_lifecycle() -> Result<(), Error> {
+        let key = Identity::generate();
+        let store = memory_store(&key);
+        let name: ItemName = "email/work".parse()?;
+
+        store.insert(&name, "hunter2")?;
+        assert!(matches!(
+            store.insert(&name, "again"),
+            Err(Error::ItemAlreadyExists(_))
+        ));
+        assert_eq!(store.get(&name, &key)?.expose_secret(), "hunter2");
+
+        store.update(&name, "correct horse")?;
+        assert_eq!(store.get(&name, &key)?.expose_secret(), "correct horse");
+
+        let binary = [0xff, 0x00, 0xfe];
+        store.update_bytes(&name, &binary)?;
+        assert_eq!(*store.get_bytes(&name, &key)?, binary);
+        assert!(matches!(store.get(&name, &key), Err(Error::NotText(_))));
+
+        let renamed: ItemName = "email/personal".parse()?;
+        store.rename(&name, &renamed)?;
+        assert_eq!(store.list()?, vec![renamed.clone()]);
+
+        store.delete(&renamed)?;
+        assert!(!store.exists(&renamed));
+        assert!(matches!(
+            store.get(&renamed, &key),
+            Err(Error::ItemNotFound(_))
+        ));
+
         Ok(())
     }
 
-    pub fn list(&self) -> Result<Vec<String>, Error> {
-        let list = self.items()?.iter().filter_map(|s| s.path().file_stem().unwrap().to_os_string().into_string().ok()).collect();
-        Ok(list)
+    #[test]
+    fn streaming_large_items() -> Result<(), Error> {
+        let key = Identity::generate();
+        let dir = std::env::temp_dir().join("passage-store-streaming");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut store = PasswordStore::init(dir, vec![key.to_public()])?;
+        store.sign_manifest(&key)?;
+        let name: ItemName = "backups/db.dump".parse()?;
+
+        // Larger than any buffer along the way, so that it takes many reads and writes
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 253) as u8).collect();
+        store.insert_from(&name, &data[..])?;
+        let mut decrypted = vec![];
+        store.get_to(&name, &key, &mut decrypted)?;
+        assert_eq!(decrypted, data);
+
+        store.update_from(&name, &data[..1000])?;
+        assert_eq!(*store.get_bytes(&name, &key)?, &data[..1000]);
+        assert!(store.verify()?.is_empty());
+
+        // Data appended after the end of the age file is caught by the manifest
+        let mut tampered = store.backend().read(&name)?;
+        tampered.extend_from_slice(b"trailing");
+        store.backend().write(&name, &tampered)?;
+        assert!(matches!(
+            store.get_bytes(&name, &key),
+            Err(Error::Tampered(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reencrypt_continues_past_failures() -> Result<(), Error> {
+        let key = Identity::generate();
+        let other = Identity::generate();
+        let new_key = Identity::generate();
+        let mut store = memory_store(&key);
+        let a: ItemName = "a".parse()?;
+        let b: ItemName = "b".parse()?;
+
+        store.insert(&a, "secret a")?;
+        store.insert(&"c".parse()?, "secret c")?;
+        store.recipients = vec![other.to_public()];
+        store.insert(&b, "secret b")?;
+        let before = store.backend().read(&b)?;
+
+        store.recipients = vec![key.to_public(), new_key.to_public()];
+        let calls = AtomicUsize::new(0);
+        let failures = store.reencrypt_with_progress(&key, |progress| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            assert_eq!(progress.total, 3);
+        })?;
+        assert_eq!(calls.into_inner(), 4);
+
+        // b is not encrypted to key, so it is left as it was
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, b);
+        assert_eq!(store.backend().read(&b)?, before);
+        assert_eq!(store.get(&a, &new_key)?.expose_secret(), "secret a");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reencrypt_to_new_recipients() -> Result<(), Error> {
+        let key = Identity::generate();
+        let new_key = Identity::generate();
+        let mut store = memory_store(&key);
+        let name: ItemName = "a".parse()?;
+
+        store.insert(&name, "secret")?;
+        store.recipients = vec![new_key.to_public()];
+        assert!(store.reencrypt(&key)?.is_empty());
+
+        assert_eq!(store.get(&name, &new_key)?.expose_secret(), "secret");
+        assert!(store.get(&name, &key).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_names() -> Result<(), Error> {
+        let key = Identity::generate();
+        let mut store = memory_store(&key);
+        let visible: ItemName = "bank".parse()?;
+        store.insert(&visible, "before")?;
+
+        store.hide_names(&key)?;
+        let hidden: ItemName = "email/work".parse()?;
+        store.insert(&hidden, "after")?;
+        store.rename(&visible, &"money/bank".parse()?)?;
+
+        // The backend only sees random IDs
+        let ids = store.backend().list()?;
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| id.as_str().len() == 32));
+
+        // A fresh view of the same backend needs the key to find anything
+        let backend = std::mem::replace(&mut store.backend, Box::new(MemoryBackend::new()));
+        let reopened = PasswordStore::with_backend(backend);
+        assert!(reopened.hides_names());
+        assert!(matches!(reopened.list(), Err(Error::SecretKeyRequired)));
+
+        assert_eq!(reopened.get(&hidden, &key)?.expose_secret(), "after");
+        assert_eq!(
+            reopened.list()?,
+            vec![hidden.clone(), "money/bank".parse()?]
+        );
+        assert_eq!(reopened.find("BANK")?, vec!["money/bank".parse()?]);
+
+        reopened.delete(&hidden)?;
+        assert_eq!(reopened.backend().list()?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_detects_tampering() -> Result<(), Error> {
+        let key = Identity::generate();
+        let mut store = memory_store(&key);
+        let a: ItemName = "a".parse()?;
+        let b: ItemName = "b".parse()?;
+        store.insert(&a, "secret a")?;
+        store.sign_manifest(&key)?;
+        store.insert(&b, "secret b")?;
+        assert!(store.verify()?.is_empty());
+
+        // Someone else replaces an item with their own ciphertext
+        let forged = crypt::encrypt_with_keys(b"forged", &store.recipients)?;
+        store.backend().write(&a, &forged)?;
+        assert!(matches!(store.get(&a, &key), Err(Error::Tampered(_))));
+        assert_eq!(store.get(&b, &key)?.expose_secret(), "secret b");
+        assert_eq!(store.verify()?, vec!["a was modified".to_string()]);
+
+        // ...or adds themselves as a recipient
+        let attacker = Identity::generate();
+        let public_keys = format!("{}\n{}\n", key.to_public(), attacker.to_public());
+        store
+            .backend()
+            .write_meta("public-keys", public_keys.as_bytes())?;
+        assert!(matches!(
+            store.insert(&"c".parse()?, "secret c"),
+            Err(Error::Tampered(_))
+        ));
+
+        // Re-signing accepts the current contents
+        let version = store.manifest()?.unwrap().version;
+        store.sign_manifest(&key)?;
+        assert!(store.verify()?.is_empty());
+        assert!(store.manifest()?.unwrap().version > version);
+
+        // The manifest key is only encrypted to the recipients
+        let pinned = store.manifest()?.unwrap();
+        let backend = std::mem::replace(&mut store.backend, Box::new(MemoryBackend::new()));
+        let mut other = PasswordStore::with_backend(backend);
+        assert!(other.unlock(&attacker).is_err());
+
+        // A manifest signed with a key of the attacker's own is rejected by an unlocked store, and
+        // by the pin of a reopened store
+        let dir = tempfile::tempdir()?;
+        let versions = dir.path().join("versions");
+        crate::manifest::check_version(&versions, "store", &pinned)?;
+        other.unlock(&key)?;
+        let forged_key = ManifestKey::generate();
+        let forged = crypt::encrypt_with_keys(forged_key.as_bytes(), &other.recipients)?;
+        other.backend().write_meta("manifest-key", &forged)?;
+        let manifest = other.manifest()?.unwrap();
+        other
+            .backend()
+            .write_meta("manifest", &manifest.sign(&forged_key))?;
+        assert!(matches!(other.verify(), Err(Error::InvalidManifest)));
+
+        let backend = std::mem::replace(&mut other.backend, Box::new(MemoryBackend::new()));
+        let reopened = PasswordStore::with_backend(backend);
+        reopened.unlock(&key)?;
+        assert!(matches!(
+            crate::manifest::check_version(&versions, "store", &reopened.manifest()?.unwrap()),
+            Err(Error::UntrustedManifest)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_is_shared_by_recipients() -> Result<(), Error> {
+        let key = Identity::generate();
+        let other = Identity::generate();
+        let mut store = memory_store(&key);
+        let a: ItemName = "a".parse()?;
+        store.insert(&a, "secret a")?;
+        store.hide_names(&key)?;
+        store.sign_manifest(&key)?;
+
+        store.add_recipients(Some(other.to_public()));
+        store.save_recipients()?;
+        assert!(store.reencrypt(&key)?.is_empty());
+
+        // The second recipient can read, list and modify the store without the owner's key
+        let backend = std::mem::replace(&mut store.backend, Box::new(MemoryBackend::new()));
+        let mut shared = PasswordStore::with_backend(backend);
+        assert!(shared.needs_unlock());
+        shared.unlock(&other)?;
+        assert_eq!(shared.get(&a, &other)?.expose_secret(), "secret a");
+        assert_eq!(shared.list()?, vec![a.clone()]);
+
+        let b: ItemName = "b".parse()?;
+        shared.insert(&b, "secret b")?;
+        assert!(shared.verify()?.is_empty());
+
+        // ...which the owner accepts
+        let backend = std::mem::replace(&mut shared.backend, Box::new(MemoryBackend::new()));
+        let owned = PasswordStore::with_backend(backend);
+        owned.unlock(&key)?;
+        assert_eq!(owned.get(&b, &key)?.expose_secret(), "secret b");
+        assert!(owned.verify()?.is_empty());
+
+        Ok(())
     }
 }